@@ -0,0 +1,232 @@
+use std::collections::VecDeque;
+
+use candid::CandidType;
+use ic_ledger_types::Tokens;
+use num_format::{Locale, ToFormattedString};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    ledger::{Block, Operation},
+    whalealert, State,
+};
+
+const HOUR_NANOS: u64 = 60 * 60 * 1_000_000_000;
+// Hourly statistics kept in the state (30 days).
+const MAX_HOURS: usize = 30 * 24;
+// Number of preceding hours the baseline is computed from (7 days).
+const BASELINE_HOURS: u64 = 7 * 24;
+// No alerts are raised before the baseline covers at least a day.
+const MIN_BASELINE_HOURS: u64 = 24;
+// Number of standard deviations an hour must deviate from the baseline mean to be unusual.
+const MAX_DEVIATION: f64 = 4.0;
+
+/// A named metric of the hourly statistics with its renderer.
+type Metric = (&'static str, fn(&HourStats) -> u64, fn(u64) -> String);
+
+/// Ledger activity within one hour.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct HourStats {
+    /// Hours since the epoch.
+    pub hour: u64,
+    pub transactions: u64,
+    /// Total amount moved in e8s.
+    pub volume: u64,
+    /// Number of distinct accounts involved.
+    pub accounts: u64,
+}
+
+/// Accounts the block to the statistics of its hour.
+pub fn record(state: &mut State, block: &Block) {
+    let hour = block.timestamp.timestamp_nanos / HOUR_NANOS;
+    if state.ledger_stats.back().map(|stats| stats.hour) != Some(hour) {
+        state.ledger_stats.push_back(HourStats {
+            hour,
+            ..Default::default()
+        });
+        state.hour_accounts.clear();
+        while state.ledger_stats.len() > MAX_HOURS {
+            state.ledger_stats.pop_front();
+        }
+    }
+    if let Some(operation) = block.transaction.operation.as_ref() {
+        for account in whalealert::accounts(operation) {
+            state.hour_accounts.insert(account.to_string());
+        }
+    }
+    let accounts = state.hour_accounts.len() as u64;
+    let stats = state.ledger_stats.back_mut().expect("no stats");
+    stats.transactions += 1;
+    // Approvals move no ICP, so their allowances don't count as volume.
+    stats.volume += match block.transaction.operation.as_ref() {
+        Some(Operation::Transfer { amount, .. })
+        | Some(Operation::Mint { amount, .. })
+        | Some(Operation::Burn { amount, .. }) => amount.e8s(),
+        _ => 0,
+    };
+    stats.accounts = accounts;
+}
+
+fn icp(e8s: u64) -> String {
+    (e8s / Tokens::SUBDIVIDABLE_BY).to_formatted_string(&Locale::de_CH)
+}
+
+/// Compares the metrics of an hour with the baseline and describes those deviating strongly.
+fn deviations(baseline: &[&HourStats], stats: &HourStats) -> Vec<String> {
+    let metrics: [Metric; 3] = [
+        (
+            "Transactions",
+            |s| s.transactions,
+            |v| v.to_formatted_string(&Locale::de_CH),
+        ),
+        ("Volume", |s| s.volume, |v| format!("{} ICP", icp(v))),
+        (
+            "Unique accounts",
+            |s| s.accounts,
+            |v| v.to_formatted_string(&Locale::de_CH),
+        ),
+    ];
+    let n = baseline.len() as f64;
+    metrics
+        .iter()
+        .filter_map(|(name, metric, render)| {
+            let mean = baseline.iter().map(|s| metric(s) as f64).sum::<f64>() / n;
+            let variance = baseline
+                .iter()
+                .map(|s| (metric(s) as f64 - mean).powi(2))
+                .sum::<f64>()
+                / n;
+            let value = metric(stats) as f64;
+            // Guard against flat baselines, where any change would be infinitely unusual.
+            let deviation = (value - mean) / variance.sqrt().max(mean * 0.01).max(1.0);
+            (deviation.abs() >= MAX_DEVIATION).then(|| {
+                format!(
+                    "- {}: `{}` vs. `{}` on average ({:+.1}σ)",
+                    name,
+                    render(value as u64),
+                    render(mean.round() as u64),
+                    deviation
+                )
+            })
+        })
+        .collect()
+}
+
+/// Returns the statistics of the hours preceding the given one within the baseline window, with
+/// zero activity for hours without blocks. Hours before the first recorded one are left out.
+fn baseline(stats: &VecDeque<HourStats>, hour: u64) -> Vec<HourStats> {
+    let first = stats.front().map(|stats| stats.hour).unwrap_or(hour);
+    (hour.saturating_sub(BASELINE_HOURS).max(first)..hour)
+        .map(|hour| {
+            stats
+                .iter()
+                .find(|stats| stats.hour == hour)
+                .cloned()
+                .unwrap_or(HourStats {
+                    hour,
+                    ..Default::default()
+                })
+        })
+        .collect()
+}
+
+/// Checks all completed hours not checked yet against the baseline of their preceding hours and
+/// returns an alert for those with unusual activity.
+pub fn check(state: &mut State) -> Option<String> {
+    let stats: &VecDeque<HourStats> = &state.ledger_stats;
+    let mut lines = Vec::new();
+    // The last hour might still be in progress.
+    for i in 0..stats.len().saturating_sub(1) {
+        let current = &stats[i];
+        if current.hour <= state.last_checked_hour {
+            continue;
+        }
+        let baseline = baseline(stats, current.hour);
+        if (baseline.len() as u64) < MIN_BASELINE_HOURS {
+            continue;
+        }
+        let deviations = deviations(&baseline.iter().collect::<Vec<_>>(), current);
+        if !deviations.is_empty() {
+            lines.push(format!(
+                "Between {:02}:00 and {:02}:00 UTC:\n\n{}",
+                current.hour % 24,
+                (current.hour + 1) % 24,
+                deviations.join("\n")
+            ));
+        }
+    }
+    if let Some(stats) = stats.iter().rev().nth(1) {
+        state.last_checked_hour = state.last_checked_hour.max(stats.hour);
+    }
+    (!lines.is_empty()).then(|| {
+        format!(
+            "📈 #UnusualActivity on the ICP ledger compared to the last {} days\n\n{}",
+            BASELINE_HOURS / 24,
+            lines.join("\n\n")
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{baseline, deviations, HourStats};
+    use std::collections::VecDeque;
+
+    #[test]
+    fn test_deviations() {
+        let e8s = 100_000_000;
+        let baseline = (0..50)
+            .map(|hour| HourStats {
+                hour,
+                transactions: 1_000 + hour % 5 * 10,
+                volume: 100_000 * e8s,
+                accounts: 500,
+            })
+            .collect::<Vec<_>>();
+        let baseline = baseline.iter().collect::<Vec<_>>();
+        let normal = HourStats {
+            hour: 50,
+            transactions: 1_030,
+            volume: 100_500 * e8s,
+            accounts: 503,
+        };
+        assert!(deviations(&baseline, &normal).is_empty());
+
+        let burst = HourStats {
+            hour: 51,
+            transactions: 5_000,
+            volume: 100_000 * e8s,
+            accounts: 2_500,
+        };
+        let result = deviations(&baseline, &burst);
+        assert_eq!(result.len(), 2);
+        assert!(result[0].starts_with("- Transactions: `5’000` vs. `1’020` on average (+"));
+        assert_eq!(
+            result[1],
+            "- Unique accounts: `2’500` vs. `500` on average (+400.0σ)"
+        );
+    }
+
+    #[test]
+    fn test_baseline() {
+        let stats = [10, 11, 14, 200, 300]
+            .iter()
+            .map(|&hour| HourStats {
+                hour,
+                transactions: 1,
+                ..Default::default()
+            })
+            .collect::<VecDeque<_>>();
+        let hours = |hour| {
+            baseline(&stats, hour)
+                .iter()
+                .map(|stats| (stats.hour, stats.transactions))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(hours(10), vec![]);
+        assert_eq!(hours(15), vec![(10, 1), (11, 1), (12, 0), (13, 0), (14, 1)]);
+        let window = hours(300);
+        assert_eq!(window.len(), 168);
+        assert_eq!(window[0], (132, 0));
+        assert_eq!(window[68], (200, 1));
+    }
+}
@@ -0,0 +1,146 @@
+use crate::{mutate, read, State};
+
+use candid::{CandidType, Principal};
+use num_format::{Locale, ToFormattedString};
+use serde::Deserialize;
+
+const XRC_CANISTER_ID: &str = "uf6dk-hyaaa-aaaaq-qaaaq-cai";
+// Every call to the XRC has to be paid for; unused cycles are refunded.
+const XRC_CYCLES: u128 = 1_000_000_000;
+// Rates older than this are considered stale and ignored.
+const MAX_RATE_AGE_SECS: u64 = 24 * 60 * 60;
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+enum AssetClass {
+    Cryptocurrency,
+    FiatCurrency,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct Asset {
+    symbol: String,
+    class: AssetClass,
+}
+
+#[derive(CandidType, Deserialize)]
+struct GetExchangeRateRequest {
+    base_asset: Asset,
+    quote_asset: Asset,
+    timestamp: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct ExchangeRateMetadata {
+    decimals: u32,
+}
+
+#[derive(CandidType, Deserialize)]
+struct ExchangeRate {
+    timestamp: u64,
+    rate: u64,
+    metadata: ExchangeRateMetadata,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+enum ExchangeRateError {
+    AnonymousPrincipalNotAllowed,
+    Pending,
+    CryptoBaseAssetNotFound,
+    CryptoQuoteAssetNotFound,
+    StablecoinRateNotFound,
+    StablecoinRateTooFewRates,
+    StablecoinRateZeroRate,
+    ForexInvalidTimestamp,
+    ForexBaseAssetNotFound,
+    ForexQuoteAssetNotFound,
+    ForexAssetsNotFound,
+    RateLimited,
+    NotEnoughCycles,
+    FailedToAcceptCycles,
+    InconsistentRatesReceived,
+    Other { code: u32, description: String },
+}
+
+/// Fetches the current USD rates of the given crypto assets and caches them in the state.
+pub async fn refresh(symbols: &[&str]) -> Result<(), String> {
+    let xrc = Principal::from_text(XRC_CANISTER_ID).expect("invalid XRC canister id");
+    let mut errors = Vec::new();
+    for symbol in symbols {
+        let request = GetExchangeRateRequest {
+            base_asset: Asset {
+                symbol: symbol.to_string(),
+                class: AssetClass::Cryptocurrency,
+            },
+            quote_asset: Asset {
+                symbol: "USD".into(),
+                class: AssetClass::FiatCurrency,
+            },
+            timestamp: None,
+        };
+        let result: Result<(Result<ExchangeRate, ExchangeRateError>,), _> =
+            ic_cdk::api::call::call_with_payment128(
+                xrc,
+                "get_exchange_rate",
+                (request,),
+                XRC_CYCLES,
+            )
+            .await;
+        match result {
+            Ok((Ok(rate),)) => mutate(|s| {
+                let value = rate.rate as f64 / 10_f64.powi(rate.metadata.decimals as i32);
+                s.usd_rates
+                    .insert(symbol.to_string(), (rate.timestamp, value));
+            }),
+            Ok((Err(err),)) => errors.push(format!("{}: {:?}", symbol, err)),
+            Err(err) => errors.push(format!("{}: {:?}", symbol, err)),
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "couldn't fetch exchange rates: {}",
+            errors.join(", ")
+        ))
+    }
+}
+
+/// Returns the USD value of the given amount of tokens, if a recent rate is known.
+pub fn usd_value(state: &State, symbol: &str, tokens: f64) -> Option<f64> {
+    let now = ic_cdk::api::time() / 1_000_000_000;
+    state
+        .usd_rates
+        .get(symbol)
+        .filter(|(timestamp, _)| timestamp + MAX_RATE_AGE_SECS >= now)
+        .map(|(_, rate)| tokens * rate)
+}
+
+/// Checks whether the amount exceeds the USD threshold, falling back to the token threshold if no
+/// recent rate is known.
+pub fn exceeds(
+    state: &State,
+    symbol: &str,
+    tokens: f64,
+    threshold_usd: u64,
+    fallback_tokens: u64,
+) -> bool {
+    match usd_value(state, symbol, tokens) {
+        Some(value) => value >= threshold_usd as f64,
+        None => tokens >= fallback_tokens as f64,
+    }
+}
+
+/// Renders a USD value as ` (~$1'234)`.
+pub fn usd(value: f64) -> String {
+    format!(
+        " (~${})",
+        (value as u64).to_formatted_string(&Locale::de_CH)
+    )
+}
+
+/// Renders the USD value of the amount, or nothing if no recent rate is known.
+pub fn usd_suffix(symbol: &str, tokens: f64) -> String {
+    read(|s| usd_value(s, symbol, tokens))
+        .map(usd)
+        .unwrap_or_default()
+}
@@ -1,18 +1,21 @@
+use candid::CandidType;
 use ic_cdk::api::management_canister::http_request::{
     http_request, CanisterHttpRequestArgument, HttpMethod, HttpResponse, TransformArgs,
     TransformContext,
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-use crate::{mutate, read, schedule_message};
+use crate::{controller_only, mutate, read, schedule_message, schedule_reply};
 
 #[derive(Deserialize)]
 struct Story {
     id: u32,
     title: String,
+    // Ask HN and similar stories come without a url.
+    #[serde(default)]
     url: String,
     // kids: Vec<u32>,
-    // score: u32,
+    score: u32,
     // time: u64,
     // descendants: u32,
     // by: String,
@@ -20,6 +23,30 @@ struct Story {
 
 const CYCLES: u128 = 30_000_000_000;
 const MAX_STORIES_PER_DAY: usize = 6;
+const REALM: &str = "TECHNOLOGY";
+
+/// Defines how the stories of a day are posted.
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug)]
+pub enum Mode {
+    /// Every story becomes its own post.
+    Stories,
+    /// All stories are collected into one ranked digest post; if `threaded` is set, every story
+    /// is additionally posted as a reply to the digest.
+    Digest { threaded: bool },
+}
+
+impl Default for Mode {
+    fn default() -> Self {
+        Mode::Digest { threaded: true }
+    }
+}
+
+#[ic_cdk_macros::update]
+fn set_hackernews_mode(mode: Mode) -> Result<(), String> {
+    controller_only()?;
+    mutate(|s| s.hackernews_mode = mode);
+    Ok(())
+}
 
 #[ic_cdk_macros::query]
 fn transform_hn_response(mut args: TransformArgs) -> HttpResponse {
@@ -28,6 +55,7 @@ fn transform_hn_response(mut args: TransformArgs) -> HttpResponse {
 }
 
 pub async fn go() -> Result<(), String> {
+    let mode = read(|s| s.hackernews_mode);
     let request = CanisterHttpRequestArgument {
         url: "https://hacker-news.firebaseio.com/v0/beststories.json".to_string(),
         method: HttpMethod::GET,
@@ -46,23 +74,44 @@ pub async fn go() -> Result<(), String> {
         .map_err(|err| format!("json parsing failed: {:?}", err))?;
 
     let mut last_best_story = read(|s| s.last_best_story);
-    let mut total = 0;
+    let mut stories = Vec::new();
     for id in best_stories.into_iter() {
-        if total >= MAX_STORIES_PER_DAY {
+        if stories.len() >= MAX_STORIES_PER_DAY {
             break;
         }
         if id <= last_best_story {
             continue;
         }
-        fetch_story(id).await?;
+        let story = fetch_story(id).await?;
+        if let Mode::Stories = mode {
+            mutate(|s| schedule_message(s, story_message(&story), Some(REALM.into())));
+        }
+        stories.push(story);
         last_best_story = id;
-        total += 1;
     }
     mutate(|s| s.last_best_story = last_best_story);
+
+    if let Mode::Digest { threaded } = mode {
+        if stories.is_empty() {
+            return Ok(());
+        }
+        stories.sort_by_key(|story| std::cmp::Reverse(story.score));
+        mutate(|s| {
+            let Some(digest_id) = schedule_message(s, digest_message(&stories), Some(REALM.into()))
+            else {
+                return;
+            };
+            if threaded {
+                for story in &stories {
+                    schedule_reply(s, digest_id, story_message(story), Some(REALM.into()));
+                }
+            }
+        });
+    }
     Ok(())
 }
 
-async fn fetch_story(id: u64) -> Result<(), String> {
+async fn fetch_story(id: u64) -> Result<Story, String> {
     let request = CanisterHttpRequestArgument {
         max_response_bytes: Some(3000),
         url: format!("https://hacker-news.firebaseio.com/v0/item/{}.json", id),
@@ -72,16 +121,55 @@ async fn fetch_story(id: u64) -> Result<(), String> {
     let (response,) = http_request(request, CYCLES)
         .await
         .map_err(|err| format!("http_request failed: {:?}", err))?;
-    let Story { id, title, url, .. } = serde_json::from_slice(&response.body)
-        .map_err(|err| format!("json parsing failed: {:?}", err))?;
-    let publisher = url::Url::parse(&url)
+    serde_json::from_slice(&response.body).map_err(|err| format!("json parsing failed: {:?}", err))
+}
+
+fn comments_link(id: u32) -> String {
+    format!("https://news.ycombinator.com/item?id={}", id)
+}
+
+fn story_url(story: &Story) -> String {
+    if story.url.is_empty() {
+        comments_link(story.id)
+    } else {
+        story.url.clone()
+    }
+}
+
+fn domain(url: &str) -> String {
+    url::Url::parse(url)
         .ok()
         .and_then(|u| u.host_str().map(|host| host.to_string()))
-        .unwrap_or_default();
-    let message = format!(
-        "# #HackerNews: [{}]({})\nFrom {}, [Comments](https://news.ycombinator.com/item?id={})",
-        title, url, publisher, id
-    );
-    mutate(|s| schedule_message(s, message, Some("TECHNOLOGY".into())));
-    Ok(())
+        .unwrap_or_else(|| "news.ycombinator.com".into())
+}
+
+fn story_message(story: &Story) -> String {
+    let url = story_url(story);
+    format!(
+        "# #HackerNews: [{}]({})\nFrom {}, [Comments]({})",
+        story.title,
+        url,
+        domain(&url),
+        comments_link(story.id)
+    )
+}
+
+fn digest_message(stories: &[Story]) -> String {
+    let lines = stories
+        .iter()
+        .enumerate()
+        .map(|(i, story)| {
+            let url = story_url(story);
+            format!(
+                "{}. [{}]({}) ({}), {} points, [Comments]({})",
+                i + 1,
+                story.title,
+                url,
+                domain(&url),
+                story.score,
+                comments_link(story.id)
+            )
+        })
+        .collect::<Vec<_>>();
+    format!("# #HackerNews Daily Digest\n\n{}", lines.join("\n"))
 }
@@ -0,0 +1,426 @@
+use crate::{mutate, read};
+
+use super::{exchangerate, labels, memo, whalealert};
+use candid::{CandidType, Nat, Principal};
+use num_format::{Locale, ToFormattedString};
+use serde::Deserialize;
+use std::convert::TryFrom;
+
+const BATCH_SIZE: u64 = 1000;
+
+/// An ICRC-1 ledger supporting the ICRC-3 block log.
+pub struct Ledger {
+    pub canister_id: &'static str,
+    pub symbol: &'static str,
+    pub decimals: u32,
+    /// Symbol of the asset in the Exchange Rate Canister.
+    pub rate_symbol: &'static str,
+    /// Minimal USD value triggering an alert.
+    pub threshold_usd: u64,
+    /// Minimal amount of whole tokens triggering an alert if no recent USD rate is known.
+    pub threshold: u64,
+    pub realm: &'static str,
+    pub hashtag: &'static str,
+}
+
+pub const LEDGERS: &[Ledger] = &[
+    Ledger {
+        canister_id: "mxzaz-hqaaa-aaaar-qaada-cai",
+        symbol: "ckBTC",
+        rate_symbol: "BTC",
+        threshold_usd: 1_000_000,
+        decimals: 8,
+        threshold: 10,
+        realm: "ICP",
+        hashtag: "ckBTC",
+    },
+    Ledger {
+        canister_id: "ss2fx-dyaaa-aaaar-qacoq-cai",
+        symbol: "ckETH",
+        rate_symbol: "ETH",
+        threshold_usd: 1_000_000,
+        decimals: 18,
+        threshold: 300,
+        realm: "ICP",
+        hashtag: "ckETH",
+    },
+    Ledger {
+        canister_id: "xevnm-gaaaa-aaaar-qafnq-cai",
+        symbol: "ckUSDC",
+        rate_symbol: "USDC",
+        threshold_usd: 1_000_000,
+        decimals: 6,
+        threshold: 1_000_000,
+        realm: "ICP",
+        hashtag: "ckUSDC",
+    },
+];
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum Value {
+    Blob(Vec<u8>),
+    Text(String),
+    Nat(Nat),
+    Int(candid::Int),
+    Array(Vec<Value>),
+    Map(Vec<(String, Value)>),
+}
+
+#[derive(CandidType, Deserialize)]
+struct GetBlocksArgs {
+    start: Nat,
+    length: Nat,
+}
+
+#[derive(CandidType, Deserialize)]
+struct BlockWithId {
+    id: Nat,
+    block: Value,
+}
+
+// The callback of archived blocks is omitted; archives are resolved via `icrc3_get_archives`.
+#[derive(CandidType, Deserialize)]
+struct ArchivedBlocks {
+    args: Vec<GetBlocksArgs>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct GetBlocksResult {
+    log_length: Nat,
+    blocks: Vec<BlockWithId>,
+    archived_blocks: Vec<ArchivedBlocks>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct GetArchivesArgs {
+    from: Option<Principal>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct ArchiveInfo {
+    canister_id: Principal,
+    start: Nat,
+    end: Nat,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<Vec<u8>>,
+}
+
+/// A transaction decoded from a generic ICRC-3 block.
+#[derive(Debug, PartialEq)]
+pub struct Transaction {
+    pub op: String,
+    pub from: Option<Account>,
+    pub to: Option<Account>,
+    pub spender: Option<Account>,
+    pub amount: u128,
+    pub memo: Option<Vec<u8>>,
+}
+
+pub async fn go(ledger: &Ledger) -> Result<(), String> {
+    let canister_id = Principal::from_text(ledger.canister_id)
+        .map_err(|err| format!("invalid ledger id: {:?}", err))?;
+    let Some(start) = read(|s| s.icrc_cursors.get(ledger.canister_id).copied()) else {
+        // Start tailing from the current end of the log.
+        let response = get_blocks(canister_id, 0, 0).await?;
+        let log_length = to_u64(&response.log_length)?;
+        mutate(|s| s.icrc_cursors.insert(ledger.canister_id.into(), log_length));
+        return Ok(());
+    };
+
+    let mut cursor = start;
+    let mut archives = None;
+    let mut msgs = Vec::new();
+    for _ in 0..100 {
+        let response = get_blocks(canister_id, cursor, BATCH_SIZE).await?;
+        let mut blocks = Vec::new();
+        // Set if an archive returned fewer blocks than requested.
+        let mut incomplete = false;
+        for range in &response.archived_blocks {
+            if archives.is_none() {
+                archives = Some(get_archives(canister_id).await?);
+            }
+            for args in &range.args {
+                let (start, length) = (to_u64(&args.start)?, to_u64(&args.length)?);
+                let archive = archives
+                    .iter()
+                    .flatten()
+                    .find(|archive| {
+                        to_u64(&archive.start).unwrap_or(u64::MAX) <= start
+                            && start <= to_u64(&archive.end).unwrap_or_default()
+                    })
+                    .ok_or(format!("no archive found for block {}", start))?;
+                let archived = get_blocks(archive.canister_id, start, length).await?.blocks;
+                incomplete |= (archived.len() as u64) < length;
+                blocks.extend(archived);
+            }
+        }
+        blocks.extend(response.blocks);
+        let mut blocks = blocks
+            .into_iter()
+            .map(|BlockWithId { id, block }| to_u64(&id).map(|id| (id, block)))
+            .collect::<Result<Vec<_>, _>>()?;
+        blocks.sort_by_key(|(id, _)| *id);
+
+        let batch_start = cursor;
+        for (id, block) in &blocks {
+            if *id < cursor {
+                continue;
+            }
+            // Never skip blocks: stop at the first gap and retry from there next time.
+            if *id > cursor {
+                break;
+            }
+            cursor = id + 1;
+            let Some(tx) = decode_transaction(block) else {
+                continue;
+            };
+            if read(|s| {
+                exchangerate::exceeds(
+                    s,
+                    ledger.rate_symbol,
+                    whole_tokens(tx.amount, ledger.decimals),
+                    ledger.threshold_usd,
+                    ledger.threshold,
+                )
+            }) {
+                msgs.push(render(ledger, &tx));
+            }
+        }
+        if incomplete || cursor == batch_start || cursor >= to_u64(&response.log_length)? {
+            break;
+        }
+    }
+
+    mutate(|s| {
+        s.icrc_cursors.insert(ledger.canister_id.into(), cursor);
+        s.logs.push_back(format!(
+            "{} blocks pulled: {} (start: {}, next_block: {})",
+            ledger.symbol,
+            cursor - start,
+            start,
+            cursor
+        ));
+        whalealert::schedule_thread(
+            s,
+            &format!("🚨 #WhaleAlert #{}", ledger.hashtag),
+            &msgs,
+            ledger.realm,
+        );
+    });
+    Ok(())
+}
+
+async fn get_blocks(
+    canister_id: Principal,
+    start: u64,
+    length: u64,
+) -> Result<GetBlocksResult, String> {
+    let args = vec![GetBlocksArgs {
+        start: start.into(),
+        length: length.into(),
+    }];
+    let (response,): (GetBlocksResult,) = ic_cdk::call(canister_id, "icrc3_get_blocks", (args,))
+        .await
+        .map_err(|err| format!("canister call failed: {:?}", err))?;
+    Ok(response)
+}
+
+async fn get_archives(canister_id: Principal) -> Result<Vec<ArchiveInfo>, String> {
+    let (archives,): (Vec<ArchiveInfo>,) = ic_cdk::call(
+        canister_id,
+        "icrc3_get_archives",
+        (GetArchivesArgs { from: None },),
+    )
+    .await
+    .map_err(|err| format!("canister call failed: {:?}", err))?;
+    Ok(archives)
+}
+
+fn to_u64(n: &Nat) -> Result<u64, String> {
+    u64::try_from(&n.0).map_err(|err| format!("couldn't convert {}: {:?}", n, err))
+}
+
+fn field<'a>(map: &'a Value, name: &str) -> Option<&'a Value> {
+    match map {
+        Value::Map(fields) => fields
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value),
+        _ => None,
+    }
+}
+
+fn decode_account(value: &Value) -> Option<Account> {
+    let Value::Array(parts) = value else {
+        return None;
+    };
+    let owner = match parts.first()? {
+        Value::Blob(bytes) => Principal::try_from_slice(bytes).ok()?,
+        _ => return None,
+    };
+    let subaccount = match parts.get(1) {
+        Some(Value::Blob(bytes)) => Some(bytes.clone()),
+        _ => None,
+    };
+    Some(Account { owner, subaccount })
+}
+
+/// Decodes the transaction of an ICRC-3 block, supporting both the legacy `tx.op` field and the
+/// top-level `btype` field.
+pub fn decode_transaction(block: &Value) -> Option<Transaction> {
+    let tx = field(block, "tx")?;
+    let op = match (field(tx, "op"), field(block, "btype")) {
+        (Some(Value::Text(op)), _) => op.clone(),
+        (_, Some(Value::Text(btype))) => match btype.as_str() {
+            "1xfer" | "2xfer" => "xfer".into(),
+            "1mint" => "mint".into(),
+            "1burn" => "burn".into(),
+            "2approve" => "approve".into(),
+            other => other.into(),
+        },
+        _ => return None,
+    };
+    let amount = match field(tx, "amt") {
+        Some(Value::Nat(amount)) => u128::try_from(&amount.0).ok()?,
+        _ => 0,
+    };
+    Some(Transaction {
+        op,
+        from: field(tx, "from").and_then(decode_account),
+        to: field(tx, "to").and_then(decode_account),
+        spender: field(tx, "spender").and_then(decode_account),
+        amount,
+        memo: match field(tx, "memo") {
+            Some(Value::Blob(bytes)) => Some(bytes.clone()),
+            _ => None,
+        },
+    })
+}
+
+fn render(ledger: &Ledger, tx: &Transaction) -> String {
+    let account = |account: &Option<Account>| {
+        account
+            .as_ref()
+            .map(|account| {
+                read(|s| labels::find_icrc(s, &account.owner, account.subaccount.as_deref()))
+                    .map(|label| label.to_string())
+                    .unwrap_or_else(|| {
+                        let owner = account.owner.to_text();
+                        format!("`{}`", &owner[0..owner.len().min(5)])
+                    })
+            })
+            .unwrap_or_default()
+    };
+    let amount = format!(
+        "`{}` {}{}",
+        tokens(tx.amount, ledger.decimals),
+        ledger.symbol,
+        exchangerate::usd_suffix(ledger.rate_symbol, whole_tokens(tx.amount, ledger.decimals))
+    );
+    let memo = tx
+        .memo
+        .as_deref()
+        .and_then(memo::decode_bytes)
+        // Only transactions above the threshold are rendered.
+        .map(|memo| read(|s| memo::render(s, &memo, true)))
+        .unwrap_or_default();
+    match tx.op.as_str() {
+        "mint" => format!("- {} minted to {}{}.", amount, account(&tx.to), memo),
+        "burn" => format!("- {} burned from {}{}.", amount, account(&tx.from), memo),
+        "approve" => format!(
+            "- {} approved by {} for spending by {}.",
+            amount,
+            account(&tx.from),
+            account(&tx.spender)
+        ),
+        _ if tx.spender.is_some() => format!(
+            "- {} transferred from {} to {} by spender {}{}.",
+            amount,
+            account(&tx.from),
+            account(&tx.to),
+            account(&tx.spender),
+            memo
+        ),
+        _ => format!(
+            "- {} transferred from {} to {}{}.",
+            amount,
+            account(&tx.from),
+            account(&tx.to),
+            memo
+        ),
+    }
+}
+
+fn whole_tokens(amount: u128, decimals: u32) -> f64 {
+    amount as f64 / 10_f64.powi(decimals as i32)
+}
+
+/// Formats an amount of base units as whole tokens with two decimals.
+pub fn tokens(amount: u128, decimals: u32) -> String {
+    let unit = 10_u128.pow(decimals);
+    let whole = (amount / unit) as u64;
+    let cents = (amount % unit) * 100 / unit;
+    if cents == 0 {
+        whole.to_formatted_string(&Locale::de_CH)
+    } else {
+        format!("{}.{:02}", whole.to_formatted_string(&Locale::de_CH), cents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_transaction, tokens, Account, Transaction, Value};
+    use candid::Principal;
+
+    #[test]
+    fn test_decoding() {
+        let owner = Principal::from_text("mxzaz-hqaaa-aaaar-qaada-cai").unwrap();
+        let block = Value::Map(vec![
+            ("btype".into(), Value::Text("1xfer".into())),
+            ("ts".into(), Value::Nat(1_700_000_000_u64.into())),
+            (
+                "tx".into(),
+                Value::Map(vec![
+                    ("amt".into(), Value::Nat(1_250_000_000_u64.into())),
+                    ("memo".into(), Value::Blob(b"order 7".to_vec())),
+                    (
+                        "from".into(),
+                        Value::Array(vec![Value::Blob(owner.as_slice().to_vec())]),
+                    ),
+                    (
+                        "to".into(),
+                        Value::Array(vec![
+                            Value::Blob(owner.as_slice().to_vec()),
+                            Value::Blob(vec![1; 32]),
+                        ]),
+                    ),
+                ]),
+            ),
+        ]);
+        let bytes = candid::encode_one(&block).unwrap();
+        let block: Value = candid::decode_one(&bytes).unwrap();
+        assert_eq!(
+            decode_transaction(&block),
+            Some(Transaction {
+                op: "xfer".into(),
+                from: Some(Account {
+                    owner,
+                    subaccount: None
+                }),
+                to: Some(Account {
+                    owner,
+                    subaccount: Some(vec![1; 32])
+                }),
+                spender: None,
+                amount: 1_250_000_000,
+                memo: Some(b"order 7".to_vec()),
+            })
+        );
+        assert_eq!(tokens(1_250_000_000, 8), "12.50");
+        assert_eq!(tokens(1_000_000_000_000, 6), "1’000’000");
+    }
+}
@@ -0,0 +1,548 @@
+use std::fmt;
+
+use candid::{CandidType, Principal};
+use ic_ledger_types::{AccountIdentifier, Subaccount, DEFAULT_SUBACCOUNT};
+use serde::{Deserialize, Serialize};
+
+use crate::{controller_only, mutate, read, State};
+
+#[derive(Clone, Copy, Debug, PartialEq, CandidType, Serialize, Deserialize)]
+pub enum Category {
+    Exchange,
+    Foundation,
+    DaoTreasury,
+    KnownWhale,
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Category::Exchange => "exchange",
+            Category::Foundation => "foundation",
+            Category::DaoTreasury => "DAO treasury",
+            Category::KnownWhale => "known whale",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl std::str::FromStr for Category {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().replace(['_', ' '], "").as_str() {
+            "exchange" => Ok(Category::Exchange),
+            "foundation" => Ok(Category::Foundation),
+            "daotreasury" => Ok(Category::DaoTreasury),
+            "knownwhale" => Ok(Category::KnownWhale),
+            other => Err(format!("unknown category: {}", other)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Serialize, Deserialize)]
+pub struct Label {
+    pub name: String,
+    pub category: Category,
+}
+
+impl fmt::Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.name, self.category)
+    }
+}
+
+/// A label covering all accounts of a principal within a range of numeric subaccounts.
+#[derive(Clone, Debug, PartialEq, CandidType, Serialize, Deserialize)]
+pub struct PrincipalLabel {
+    pub principal: Principal,
+    /// Inclusive range of subaccount indices; only the default subaccount if empty.
+    pub subaccounts: Option<(u64, u64)>,
+    pub label: Label,
+}
+
+// Upper bound of derived account identifiers per principal label.
+const MAX_SUBACCOUNTS: u64 = 1000;
+
+/// An account alerting on any movement, whatever the amount.
+#[derive(Clone, Debug, PartialEq, CandidType, Serialize, Deserialize)]
+pub struct WatchedAccount {
+    pub note: String,
+    /// Realm of the alerts; the default watchlist realm is used if empty.
+    pub realm: Option<String>,
+}
+
+/// An entry of a bulk import; the account is either a hex account identifier or a principal.
+#[derive(Deserialize)]
+struct Entry {
+    account: String,
+    name: String,
+    category: Category,
+    #[serde(default)]
+    subaccounts: Option<(u64, u64)>,
+}
+
+/// Labels known before the directory was moved into the state.
+const DEFAULT_LABELS: &[(&str, &str)] = &[
+    (
+        "d3e13d4777e22367532053190b6c6ccf57444a61337e996242b1abfb52cf92c8",
+        "Binance",
+    ),
+    (
+        "220c3a33f90601896e26f76fa619fe288742df1fa75426edfaf759d39f2455a5",
+        "Binance",
+    ),
+    (
+        "935b1a3adc28fd68cacc95afcdec62e985244ce0cfbbb12cdc7d0b8d198b416d",
+        "Huobi",
+    ),
+    (
+        "e7a879ea563d273c46dd28c1584eaa132fad6f3e316615b3eb657d067f3519b5",
+        "Okex",
+    ),
+    (
+        "4dfa940def17f1427ae47378c440f10185867677109a02bc8374fc25b9dee8af",
+        "Coinbase",
+    ),
+    (
+        "a6ed987d89796f921c8a49d275ec7c9aa04e75a8fc8cd2dbaa5da799f0215ab0",
+        "Coinbase",
+    ),
+    (
+        "449ce7ad1298e2ed2781ed379aba25efc2748d14c60ede190ad7621724b9e8b2",
+        "Coinbase",
+    ),
+    (
+        "660b1680dafeedaa68c1f1f4cf8af42ed1dfb8564646efe935a2b9a48528b605",
+        "Coinbase",
+    ),
+    (
+        "dd15f3040edab88d2e277f9d2fa5cc11616ebf1442279092e37924ab7cce8a74",
+        "Coinbase",
+    ),
+    (
+        "4878d23a09b554157b31323004e1cc053567671426ca4eec7b7e835db607b965",
+        "Coinbase",
+    ),
+    (
+        "8fe706db7b08f957a15199e07761039a7718937aabcc0fe48bc380a4daf9afb0",
+        "Gate",
+    ),
+    (
+        "efa01544f509c56dd85449edf2381244a48fad1ede5183836229c00ab00d52df",
+        "KuCoin",
+    ),
+    (
+        "040834c30cdf5d7a13aae8b57d94ae2d07eefe2bc3edd8cf88298730857ac2eb",
+        "Kraken",
+    ),
+    (
+        "609d3e1e45103a82adc97d4f88c51f78dedb25701e8e51e8c4fec53448aadc29",
+        "Binance Cold Storage",
+    ),
+];
+
+/// Fills the directory with the default labels; called only on a fresh install or when migrating
+/// the original state, so that removed defaults don't come back on upgrades.
+pub fn seed(state: &mut State) {
+    for (account, name) in DEFAULT_LABELS {
+        state.labels.insert(
+            account.to_string(),
+            Label {
+                name: name.to_string(),
+                category: Category::Exchange,
+            },
+        );
+    }
+}
+
+/// Returns the numeric index of a subaccount, encoded big-endian in its last 8 bytes.
+fn subaccount_index(subaccount: &[u8; 32]) -> Option<u64> {
+    if subaccount[..24].iter().any(|b| *b != 0) {
+        return None;
+    }
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&subaccount[24..]);
+    Some(u64::from_be_bytes(bytes))
+}
+
+fn subaccount(index: u64) -> Subaccount {
+    let mut bytes = [0; 32];
+    bytes[24..].copy_from_slice(&index.to_be_bytes());
+    Subaccount(bytes)
+}
+
+fn subaccount_range(label: &PrincipalLabel) -> (u64, u64) {
+    label.subaccounts.unwrap_or((0, 0))
+}
+
+/// Rebuilds the account identifiers derived from principal labels.
+pub fn rebuild(state: &mut State) {
+    state.derived_labels.clear();
+    for entry in &state.principal_labels {
+        let (from, to) = subaccount_range(entry);
+        for index in from..=to {
+            let account = AccountIdentifier::new(&entry.principal, &subaccount(index));
+            state
+                .derived_labels
+                .insert(account.to_string(), (entry.principal, entry.label.clone()));
+        }
+    }
+}
+
+pub fn find(state: &State, account: &str) -> Option<Label> {
+    state
+        .labels
+        .get(account)
+        .or_else(|| state.derived_labels.get(account).map(|(_, label)| label))
+        .cloned()
+}
+
+/// Returns the principal owning the account if the account is derived from a principal label.
+pub fn owner(state: &State, account: &str) -> Option<Principal> {
+    state
+        .derived_labels
+        .get(account)
+        .map(|(principal, _)| *principal)
+}
+
+/// Returns the label of the given account identifier, or its short form if the account is unknown.
+pub fn resolve(state: &State, account: &str) -> String {
+    find(state, account)
+        .map(|label| label.to_string())
+        .unwrap_or_else(|| account[0..6].to_string())
+}
+
+/// Returns the label of an ICRC-1 account, matching principal labels as well as labels of the
+/// corresponding account identifier.
+pub fn find_icrc(state: &State, owner: &Principal, subaccount: Option<&[u8]>) -> Option<Label> {
+    let mut bytes = [0; 32];
+    if let Some(subaccount) = subaccount {
+        if subaccount.len() != 32 {
+            return None;
+        }
+        bytes.copy_from_slice(subaccount);
+    }
+    let index = subaccount_index(&bytes);
+    state
+        .principal_labels
+        .iter()
+        .find(|entry| {
+            let (from, to) = subaccount_range(entry);
+            &entry.principal == owner && index.map(|i| from <= i && i <= to).unwrap_or_default()
+        })
+        .map(|entry| entry.label.clone())
+        .or_else(|| {
+            let account = AccountIdentifier::new(owner, &Subaccount(bytes));
+            state.labels.get(&account.to_string()).cloned()
+        })
+}
+
+/// Accepts a hex account identifier or a principal, which stands for its default account.
+fn parse_account(account: &str) -> Result<String, String> {
+    match Principal::from_text(account.trim()) {
+        Ok(principal) => Ok(AccountIdentifier::new(&principal, &DEFAULT_SUBACCOUNT).to_string()),
+        Err(_) => validate_account(account),
+    }
+}
+
+fn validate_account(account: &str) -> Result<String, String> {
+    let account = account.trim().to_lowercase();
+    if account.len() != 64 || hex::decode(&account).is_err() {
+        return Err(format!("invalid account identifier: {}", account));
+    }
+    Ok(account)
+}
+
+/// Parses labels from JSON (a list of `{account, name, category, subaccounts}` objects) or CSV
+/// (lines of `account,name,category[,from-to]`).
+fn parse_entries(format: &str, data: &str) -> Result<Vec<Entry>, String> {
+    match format {
+        "json" => {
+            serde_json::from_str(data).map_err(|err| format!("json parsing failed: {:?}", err))
+        }
+        "csv" => data
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(
+                |line| match line.split(',').collect::<Vec<_>>().as_slice() {
+                    [account, name, category] => Ok(Entry {
+                        account: account.to_string(),
+                        name: name.trim().to_string(),
+                        category: category.parse()?,
+                        subaccounts: None,
+                    }),
+                    [account, name, category, range] => {
+                        let (from, to) = range
+                            .trim()
+                            .split_once('-')
+                            .ok_or(format!("malformed subaccount range: {}", range))?;
+                        let parse = |n: &str| {
+                            n.trim()
+                                .parse::<u64>()
+                                .map_err(|err| format!("malformed subaccount range: {:?}", err))
+                        };
+                        Ok(Entry {
+                            account: account.to_string(),
+                            name: name.trim().to_string(),
+                            category: category.parse()?,
+                            subaccounts: Some((parse(from)?, parse(to)?)),
+                        })
+                    }
+                    _ => Err(format!("malformed line: {}", line)),
+                },
+            )
+            .collect(),
+        other => Err(format!("unsupported format: {}", other)),
+    }
+}
+
+#[ic_cdk_macros::update]
+fn add_label(account: String, name: String, category: Category) -> Result<(), String> {
+    controller_only()?;
+    let account = validate_account(&account)?;
+    mutate(|s| s.labels.insert(account, Label { name, category }));
+    Ok(())
+}
+
+fn validate_range(subaccounts: Option<(u64, u64)>) -> Result<(), String> {
+    match subaccounts {
+        Some((from, to)) if from > to || to - from >= MAX_SUBACCOUNTS => {
+            Err(format!("invalid subaccount range: {}..{}", from, to))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[ic_cdk_macros::update]
+fn add_principal_label(
+    principal: Principal,
+    subaccounts: Option<(u64, u64)>,
+    name: String,
+    category: Category,
+) -> Result<(), String> {
+    controller_only()?;
+    validate_range(subaccounts)?;
+    mutate(|s| {
+        s.principal_labels
+            .retain(|entry| entry.principal != principal);
+        s.principal_labels.push(PrincipalLabel {
+            principal,
+            subaccounts,
+            label: Label { name, category },
+        });
+        rebuild(s);
+    });
+    Ok(())
+}
+
+#[ic_cdk_macros::update]
+fn remove_principal_label(principal: Principal) -> Result<(), String> {
+    controller_only()?;
+    mutate(|s| {
+        let len = s.principal_labels.len();
+        s.principal_labels
+            .retain(|entry| entry.principal != principal);
+        if s.principal_labels.len() == len {
+            return Err("no such label".to_string());
+        }
+        rebuild(s);
+        Ok(())
+    })
+}
+
+#[ic_cdk_macros::update]
+fn remove_label(account: String) -> Result<(), String> {
+    controller_only()?;
+    let account = validate_account(&account)?;
+    mutate(|s| s.labels.remove(&account))
+        .map(|_| ())
+        .ok_or("no such label".into())
+}
+
+/// Imports labels in bulk and returns the number of imported labels.
+#[ic_cdk_macros::update]
+fn import_labels(format: String, data: String) -> Result<u32, String> {
+    controller_only()?;
+    let entries = parse_entries(&format.to_lowercase(), &data)?;
+    let mut accounts = Vec::new();
+    let mut principals = Vec::new();
+    for entry in entries {
+        let label = Label {
+            name: entry.name,
+            category: entry.category,
+        };
+        match Principal::from_text(entry.account.trim()) {
+            Ok(principal) => {
+                validate_range(entry.subaccounts)?;
+                principals.push(PrincipalLabel {
+                    principal,
+                    subaccounts: entry.subaccounts,
+                    label,
+                })
+            }
+            Err(_) => accounts.push((validate_account(&entry.account)?, label)),
+        }
+    }
+    let imported = (accounts.len() + principals.len()) as u32;
+    mutate(|s| {
+        s.labels.extend(accounts);
+        for entry in principals {
+            s.principal_labels
+                .retain(|existing| existing.principal != entry.principal);
+            s.principal_labels.push(entry);
+        }
+        rebuild(s);
+    });
+    Ok(imported)
+}
+
+#[ic_cdk_macros::query]
+fn list_labels() -> Vec<(String, Label)> {
+    read(|s| {
+        s.labels
+            .iter()
+            .map(|(account, label)| (account.clone(), label.clone()))
+            .collect()
+    })
+}
+
+/// Labels a memo as rendered in alerts, e.g. `1234` or `deposit 17`.
+#[ic_cdk_macros::update]
+fn add_memo_label(memo: String, label: String) -> Result<(), String> {
+    controller_only()?;
+    let memo = memo.trim().to_string();
+    if memo.is_empty() || label.trim().is_empty() {
+        return Err("memo and label must not be empty".into());
+    }
+    mutate(|s| s.memo_labels.insert(memo, label.trim().to_string()));
+    Ok(())
+}
+
+#[ic_cdk_macros::update]
+fn remove_memo_label(memo: String) -> Result<(), String> {
+    controller_only()?;
+    mutate(|s| s.memo_labels.remove(memo.trim()))
+        .map(|_| ())
+        .ok_or("no such memo".into())
+}
+
+#[ic_cdk_macros::query]
+fn list_memo_labels() -> Vec<(String, String)> {
+    read(|s| {
+        s.memo_labels
+            .iter()
+            .map(|(memo, label)| (memo.clone(), label.clone()))
+            .collect()
+    })
+}
+
+#[ic_cdk_macros::update]
+fn add_watched_account(account: String, note: String, realm: Option<String>) -> Result<(), String> {
+    controller_only()?;
+    let account = parse_account(&account)?;
+    mutate(|s| s.watchlist.insert(account, WatchedAccount { note, realm }));
+    Ok(())
+}
+
+#[ic_cdk_macros::update]
+fn remove_watched_account(account: String) -> Result<(), String> {
+    controller_only()?;
+    let account = parse_account(&account)?;
+    mutate(|s| s.watchlist.remove(&account))
+        .map(|_| ())
+        .ok_or("no such account".into())
+}
+
+#[ic_cdk_macros::query]
+fn list_watchlist() -> Vec<(String, WatchedAccount)> {
+    read(|s| {
+        s.watchlist
+            .iter()
+            .map(|(account, entry)| (account.clone(), entry.clone()))
+            .collect()
+    })
+}
+
+/// Registers the governance account of a neuron, so that transfers to it are reported as stakes.
+#[ic_cdk_macros::update]
+fn add_neuron_account(account: String) -> Result<(), String> {
+    controller_only()?;
+    let account = validate_account(&account)?;
+    mutate(|s| s.neuron_accounts.insert(account));
+    Ok(())
+}
+
+#[ic_cdk_macros::update]
+fn remove_neuron_account(account: String) -> Result<(), String> {
+    controller_only()?;
+    let account = validate_account(&account)?;
+    mutate(|s| s.neuron_accounts.remove(&account))
+        .then_some(())
+        .ok_or("no such account".into())
+}
+
+#[ic_cdk_macros::query]
+fn list_principal_labels() -> Vec<PrincipalLabel> {
+    read(|s| s.principal_labels.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_icrc, parse_entries, rebuild, resolve, Category, Label, PrincipalLabel};
+    use crate::State;
+    use candid::Principal;
+    use ic_ledger_types::{AccountIdentifier, Subaccount};
+
+    #[test]
+    fn test_import_parsing() {
+        let csv =
+            "d3e13d4777e22367532053190b6c6ccf57444a61337e996242b1abfb52cf92c8,Binance,exchange
+            935b1a3adc28fd68cacc95afcdec62e985244ce0cfbbb12cdc7d0b8d198b416d,DFINITY,Foundation
+            ";
+        let entries = parse_entries("csv", csv).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].name, "DFINITY");
+        assert_eq!(entries[1].category, Category::Foundation);
+
+        let json = r#"[{"account": "abc", "name": "Treasury", "category": "DaoTreasury"}]"#;
+        let entries = parse_entries("json", json).unwrap();
+        assert_eq!(entries[0].category, Category::DaoTreasury);
+
+        assert!(parse_entries("csv", "abc,Whale,shark").is_err());
+
+        let entries = parse_entries("csv", "aaaaa-aa,Treasury,dao_treasury,0-9").unwrap();
+        assert_eq!(entries[0].subaccounts, Some((0, 9)));
+    }
+
+    #[test]
+    fn test_principal_labels() {
+        let principal = Principal::from_text("mxzaz-hqaaa-aaaar-qaada-cai").unwrap();
+        let label = Label {
+            name: "Treasury".into(),
+            category: Category::DaoTreasury,
+        };
+        let mut state = State::default();
+        state.principal_labels.push(PrincipalLabel {
+            principal,
+            subaccounts: Some((0, 2)),
+            label: label.clone(),
+        });
+        rebuild(&mut state);
+
+        let mut subaccount = [0; 32];
+        subaccount[31] = 2;
+        let account = AccountIdentifier::new(&principal, &Subaccount(subaccount));
+        assert_eq!(
+            resolve(&state, &account.to_string()),
+            "Treasury (DAO treasury)"
+        );
+        assert_eq!(
+            find_icrc(&state, &principal, Some(&subaccount)),
+            Some(label.clone())
+        );
+        assert_eq!(find_icrc(&state, &principal, None), Some(label));
+        subaccount[31] = 3;
+        assert_eq!(find_icrc(&state, &principal, Some(&subaccount)), None);
+    }
+}
@@ -0,0 +1,256 @@
+//! Candid types of the ICP ledger's `query_blocks` endpoint.
+//!
+//! The types of `ic-ledger-types` predate ICRC-2 and lack the allowances of approvals and the
+//! spender of `transfer_from` transactions, hence the local definitions.
+
+use candid::{define_function, CandidType};
+use ic_ledger_types::{
+    AccountIdentifier, GetBlocksArgs, GetBlocksError, Memo, Timestamp, Tokens,
+    MAINNET_LEDGER_CANISTER_ID,
+};
+use serde::Deserialize;
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum Operation {
+    Mint {
+        to: AccountIdentifier,
+        amount: Tokens,
+    },
+    Burn {
+        from: AccountIdentifier,
+        spender: Option<AccountIdentifier>,
+        amount: Tokens,
+    },
+    /// A transfer; if the spender is set, it was executed via `transfer_from`.
+    Transfer {
+        from: AccountIdentifier,
+        to: AccountIdentifier,
+        amount: Tokens,
+        fee: Tokens,
+        spender: Option<AccountIdentifier>,
+    },
+    Approve {
+        from: AccountIdentifier,
+        spender: AccountIdentifier,
+        allowance: Tokens,
+        expected_allowance: Option<Tokens>,
+        expires_at: Option<Timestamp>,
+        fee: Tokens,
+    },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Transaction {
+    pub memo: Memo,
+    pub icrc1_memo: Option<Vec<u8>>,
+    pub operation: Option<Operation>,
+    pub created_at_time: Timestamp,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Block {
+    pub transaction: Transaction,
+    pub timestamp: Timestamp,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct BlockRange {
+    pub blocks: Vec<Block>,
+}
+
+pub type QueryArchiveResult = Result<BlockRange, GetBlocksError>;
+
+define_function!(pub QueryArchiveFn : (GetBlocksArgs) -> (QueryArchiveResult) query);
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ArchivedBlockRange {
+    pub start: u64,
+    pub length: u64,
+    pub callback: QueryArchiveFn,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct QueryBlocksResponse {
+    pub chain_length: u64,
+    pub blocks: Vec<Block>,
+    pub first_block_index: u64,
+    pub archived_blocks: Vec<ArchivedBlockRange>,
+}
+
+pub async fn query_blocks(start: u64, length: u64) -> Result<QueryBlocksResponse, String> {
+    let (response,): (QueryBlocksResponse,) = ic_cdk::call(
+        MAINNET_LEDGER_CANISTER_ID,
+        "query_blocks",
+        (GetBlocksArgs { start, length },),
+    )
+    .await
+    .map_err(|err| format!("canister call failed: {:?}", err))?;
+    Ok(response)
+}
+
+/// Returns the contiguous range of blocks starting at `start`, following the archive callbacks
+/// for blocks no longer held by the ledger. The range stops early if an archive returns fewer
+/// blocks than announced, so that no block is ever skipped.
+pub async fn fetch_blocks(start: u64, response: QueryBlocksResponse) -> Result<Vec<Block>, String> {
+    let mut blocks = Vec::new();
+    let mut archived_blocks = response.archived_blocks;
+    archived_blocks.sort_by_key(|range| range.start);
+    for range in archived_blocks {
+        if range.start != start + blocks.len() as u64 {
+            return Ok(blocks);
+        }
+        let args = GetBlocksArgs {
+            start: range.start,
+            length: range.length,
+        };
+        let (result,): (QueryArchiveResult,) = ic_cdk::call(
+            range.callback.0.principal,
+            &range.callback.0.method,
+            (args,),
+        )
+        .await
+        .map_err(|err| format!("archive call failed: {:?}", err))?;
+        let archived = result
+            .map_err(|err| format!("archive returned an error: {:?}", err))?
+            .blocks;
+        let complete = archived.len() as u64 == range.length;
+        blocks.extend(archived);
+        if !complete {
+            return Ok(blocks);
+        }
+    }
+    if response.first_block_index == start + blocks.len() as u64 {
+        blocks.extend(response.blocks);
+    }
+    Ok(blocks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Operation, QueryBlocksResponse};
+    use candid::{define_function, CandidType, Int, Principal};
+    use ic_ledger_types::{
+        AccountIdentifier, GetBlocksArgs, GetBlocksError, Memo, Timestamp, Tokens,
+        DEFAULT_SUBACCOUNT,
+    };
+    use serde::Deserialize;
+
+    // Mirrors of the types in the ICP ledger's interface.
+    #[derive(CandidType, Deserialize)]
+    enum WireOperation {
+        Approve {
+            fee: Tokens,
+            from: AccountIdentifier,
+            allowance_e8s: Int,
+            allowance: Tokens,
+            expected_allowance: Option<Tokens>,
+            expires_at: Option<Timestamp>,
+            spender: AccountIdentifier,
+        },
+        Transfer {
+            from: AccountIdentifier,
+            to: AccountIdentifier,
+            amount: Tokens,
+            fee: Tokens,
+            spender: Option<Vec<u8>>,
+        },
+    }
+
+    #[derive(CandidType, Deserialize)]
+    struct WireTransaction {
+        memo: Memo,
+        icrc1_memo: Option<Vec<u8>>,
+        operation: Option<WireOperation>,
+        created_at_time: Timestamp,
+    }
+
+    #[derive(CandidType, Deserialize)]
+    struct WireBlock {
+        parent_hash: Option<Vec<u8>>,
+        transaction: WireTransaction,
+        timestamp: Timestamp,
+    }
+
+    #[derive(CandidType, Deserialize)]
+    struct WireBlockRange {
+        blocks: Vec<WireBlock>,
+    }
+
+    type WireArchiveResult = Result<WireBlockRange, GetBlocksError>;
+
+    define_function!(WireArchiveFn : (GetBlocksArgs) -> (WireArchiveResult) query);
+
+    #[derive(CandidType, Deserialize)]
+    struct WireArchivedBlockRange {
+        start: u64,
+        length: u64,
+        callback: WireArchiveFn,
+    }
+
+    #[derive(CandidType, Deserialize)]
+    struct WireQueryBlocksResponse {
+        chain_length: u64,
+        certificate: Option<Vec<u8>>,
+        blocks: Vec<WireBlock>,
+        first_block_index: u64,
+        archived_blocks: Vec<WireArchivedBlockRange>,
+    }
+
+    #[test]
+    fn test_decoding() {
+        let account = AccountIdentifier::new(&Principal::anonymous(), &DEFAULT_SUBACCOUNT);
+        let block = |operation| WireBlock {
+            parent_hash: None,
+            transaction: WireTransaction {
+                memo: Memo(0),
+                icrc1_memo: None,
+                operation: Some(operation),
+                created_at_time: Timestamp { timestamp_nanos: 0 },
+            },
+            timestamp: Timestamp { timestamp_nanos: 0 },
+        };
+        let response = WireQueryBlocksResponse {
+            chain_length: 12,
+            certificate: None,
+            blocks: vec![
+                block(WireOperation::Approve {
+                    fee: Tokens::from_e8s(10_000),
+                    from: account,
+                    allowance_e8s: Int::from(500),
+                    allowance: Tokens::from_e8s(500),
+                    expected_allowance: None,
+                    expires_at: None,
+                    spender: account,
+                }),
+                block(WireOperation::Transfer {
+                    from: account,
+                    to: account,
+                    amount: Tokens::from_e8s(700),
+                    fee: Tokens::from_e8s(10_000),
+                    spender: Some(account.as_ref().to_vec()),
+                }),
+            ],
+            first_block_index: 10,
+            archived_blocks: vec![WireArchivedBlockRange {
+                start: 5,
+                length: 5,
+                callback: WireArchiveFn::new(Principal::anonymous(), "get_blocks".into()),
+            }],
+        };
+
+        let bytes = candid::encode_one(&response).unwrap();
+        let response: QueryBlocksResponse = candid::decode_one(&bytes).unwrap();
+        assert_eq!(response.archived_blocks[0].callback.0.method, "get_blocks");
+        assert!(matches!(
+            response.blocks[0].transaction.operation,
+            Some(Operation::Approve { allowance, .. }) if allowance.e8s() == 500
+        ));
+        assert!(matches!(
+            response.blocks[1].transaction.operation,
+            Some(Operation::Transfer {
+                spender: Some(_),
+                ..
+            })
+        ));
+    }
+}
@@ -1,6 +1,6 @@
 use std::{
     cell::RefCell,
-    collections::{HashMap, VecDeque},
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
     time::Duration,
 };
 
@@ -33,38 +33,207 @@ where
 const POSTING_FREQ_MIN: u64 = 15;
 const MAX_MSG_MEMORY: usize = 500;
 
+mod anomaly;
+mod exchangerate;
 mod hackernews;
+mod icrcalert;
+mod labels;
+mod ledger;
+mod memo;
 mod modulation;
+mod netflow;
+mod nnsmetrics;
+mod proposals;
 mod rss;
-mod watcherguru;
+mod snapshot;
+mod sns;
+mod supply;
+mod telegram;
 mod whalealert;
+mod xdrrate;
 
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+pub struct Message {
+    pub id: u64,
+    pub body: String,
+    pub realm: Option<String>,
+    /// Id of a previously scheduled message this message should be posted as a reply to.
+    pub reply_to: Option<u64>,
+    /// Blobs to attach as pairs of the blob id and the URL they're fetched from right before
+    /// posting, so that the queue doesn't hold their content.
+    pub blob_urls: Vec<(String, String)>,
+    /// Id of an existing Taggr post this message should be posted as a reply to.
+    pub parent_post_id: Option<u64>,
+}
+
+/// The state persisted across upgrades. Once a layout has been deployed, any change of it requires
+/// a new snapshot version with a migration, see `snapshot`.
 #[derive(Default, CandidType, Serialize, Deserialize)]
 pub struct State {
-    pub message_queue: VecDeque<(String, Option<String>)>,
+    pub message_queue: VecDeque<Message>,
     pub logs: VecDeque<String>,
     pub last_block: u64,
     pub last_best_story: u64,
     pub last_rss_story_timestamp: HashMap<String, u64>,
     pub modulation: i32,
     pub seen_messages: VecDeque<String>,
+    pub next_message_id: u64,
+    /// Taggr post ids of sent messages, keyed by message id.
+    pub post_ids: BTreeMap<u64, u64>,
+    /// Id of the last posted message per Telegram channel.
+    pub telegram_cursors: HashMap<String, u64>,
+    /// Next block to scan per ICRC ledger canister.
+    pub icrc_cursors: HashMap<String, u64>,
+    /// USD rates per asset symbol as pairs of the rate timestamp (in seconds) and the rate.
+    pub usd_rates: HashMap<String, (u64, f64)>,
+    /// Labels of known accounts, keyed by the hex account identifier.
+    pub labels: BTreeMap<String, labels::Label>,
+    pub principal_labels: Vec<labels::PrincipalLabel>,
+    /// Labels of the account identifiers derived from principal labels.
+    #[serde(skip)]
+    pub derived_labels: HashMap<String, (Principal, labels::Label)>,
+    /// Accounts alerting on any movement, keyed by the hex account identifier.
+    pub watchlist: BTreeMap<String, labels::WatchedAccount>,
+    /// Registered governance accounts of neurons.
+    pub neuron_accounts: BTreeSet<String>,
+    /// In- and outflows (in e8s) of the current week per exchange.
+    pub exchange_flows: BTreeMap<String, (u64, u64)>,
+    /// Net flows (in e8s) of the previous week per exchange.
+    pub last_exchange_net_flows: BTreeMap<String, i64>,
+    /// Hourly ICP ledger statistics, oldest first.
+    pub ledger_stats: VecDeque<anomaly::HourStats>,
+    /// Distinct accounts seen in the latest hour of the ledger statistics.
+    pub hour_accounts: BTreeSet<String>,
+    /// The latest hour checked for unusual ledger activity.
+    pub last_checked_hour: u64,
+    /// Labels of rendered transfer memos, e.g. deposit ids of exchanges.
+    pub memo_labels: BTreeMap<String, String>,
+    /// The latest NNS proposal seen.
+    pub last_proposal: u64,
+    /// Proposal topics included (true) or excluded (false), overriding the defaults.
+    pub proposal_topics: BTreeMap<i32, bool>,
+    /// Posted NNS proposals awaiting their outcome by proposal id.
+    pub open_proposals: BTreeMap<u64, proposals::OpenProposal>,
+    /// Names of known neurons whose votes are reported, by neuron id.
+    pub known_neurons: BTreeMap<u64, String>,
+    /// Weekly NNS governance metrics, oldest first.
+    pub governance_metrics: VecDeque<nnsmetrics::Metrics>,
+    /// Deployed SNSes by root canister id.
+    pub snses: BTreeMap<String, sns::Sns>,
+    /// SNSes whose proposals are posted, by root canister id.
+    pub sns_feeds: BTreeMap<String, sns::SnsFeed>,
+    /// ICP/XDR conversion rates of the CMC as (timestamp in seconds, XDR permyriad per ICP).
+    pub xdr_rates: VecDeque<(u64, u64)>,
+    /// The ICP/XDR conversion rate posted last.
+    pub last_announced_xdr_rate: u64,
+    /// The change of the ICP/XDR rate in percent that triggers a post, if not the default.
+    pub xdr_rate_threshold: Option<f64>,
+    /// Neuron maturity modulation samples as (timestamp in seconds, basis points).
+    pub modulation_history: VecDeque<(u64, i32)>,
+    /// ICP burned by the CMC for cycles since the last supply sample, in e8s.
+    pub icp_burned_e8s: u64,
+    /// Daily ICP supply samples, oldest first.
+    pub supply_history: VecDeque<supply::Sample>,
+    /// How the daily Hacker News stories are posted.
+    pub hackernews_mode: hackernews::Mode,
+    /// Timestamp in seconds of the last weekly exchange flow report.
+    pub last_netflow_report: u64,
+    /// Timestamp in seconds of the last weekly governance metrics report.
+    pub last_metrics_report: u64,
 }
 
-fn schedule_message<T: ToString>(state: &mut State, body: T, realm: Option<String>) {
-    let msg = body.to_string();
-    if state.seen_messages.contains(&msg) {
-        return;
+/// Schedules a new top-level post and returns the id of the scheduled message,
+/// unless the same message was already seen.
+fn schedule_message<T: ToString>(state: &mut State, body: T, realm: Option<String>) -> Option<u64> {
+    enqueue(
+        state,
+        body.to_string(),
+        realm,
+        None,
+        None,
+        Default::default(),
+    )
+}
+
+/// Schedules a new top-level post with blobs attached; the body references them as
+/// `/blob/<id>`. Blobs are given as pairs of the id and the URL of the content.
+fn schedule_message_with_blobs<T: ToString>(
+    state: &mut State,
+    body: T,
+    realm: Option<String>,
+    blob_urls: Vec<(String, String)>,
+) -> Option<u64> {
+    enqueue(state, body.to_string(), realm, None, None, blob_urls)
+}
+
+/// Schedules a reply to a previously scheduled message.
+fn schedule_reply<T: ToString>(
+    state: &mut State,
+    parent: u64,
+    body: T,
+    realm: Option<String>,
+) -> Option<u64> {
+    enqueue(
+        state,
+        body.to_string(),
+        realm,
+        Some(parent),
+        None,
+        Default::default(),
+    )
+}
+
+/// Schedules a reply to an existing Taggr post.
+fn schedule_reply_to_post<T: ToString>(
+    state: &mut State,
+    post_id: u64,
+    body: T,
+    realm: Option<String>,
+) -> Option<u64> {
+    enqueue(
+        state,
+        body.to_string(),
+        realm,
+        None,
+        Some(post_id),
+        Default::default(),
+    )
+}
+
+fn enqueue(
+    state: &mut State,
+    body: String,
+    realm: Option<String>,
+    reply_to: Option<u64>,
+    parent_post_id: Option<u64>,
+    blob_urls: Vec<(String, String)>,
+) -> Option<u64> {
+    if state.seen_messages.contains(&body) {
+        return None;
     }
-    state.message_queue.push_back((msg.clone(), realm));
-    state.seen_messages.push_front(msg);
+    let id = state.next_message_id;
+    state.next_message_id += 1;
+    state.seen_messages.push_front(body.clone());
     while state.seen_messages.len() > MAX_MSG_MEMORY {
         state.seen_messages.pop_back();
     }
+    state.message_queue.push_back(Message {
+        id,
+        body,
+        realm,
+        reply_to,
+        blob_urls,
+        parent_post_id,
+    });
+    Some(id)
 }
 
-async fn send_message<T: ToString>(body: T, realm: Option<String>) -> Result<u64, String> {
-    let blobs: Vec<(String, Vec<u8>)> = Default::default();
-    let parent: Option<u64> = None;
+async fn send_message<T: ToString>(
+    body: T,
+    realm: Option<String>,
+    parent: Option<u64>,
+    blobs: Vec<(String, Vec<u8>)>,
+) -> Result<u64, String> {
     let poll: Option<Vec<u8>> = None;
     let result: CallResult<(Result<u64, String>,)> = ic_cdk::call(
         Principal::from_text("6qfxa-ryaaa-aaaai-qbhsq-cai").unwrap(),
@@ -88,14 +257,42 @@ fn info(opcode: String) -> Vec<String> {
             vec![
                 format!("Logs: {}", s.logs.len(),),
                 format!("LastBlock: {}", s.last_block,),
+                format!(
+                    "LastProposal: {}, open: {:?}",
+                    s.last_proposal,
+                    s.open_proposals.keys().collect::<Vec<_>>()
+                ),
+                format!("GovernanceMetrics: {}", s.governance_metrics.len()),
+                format!("SNSes: {}, feeds: {}", s.snses.len(), s.sns_feeds.len()),
+                format!("ICRCCursors: {:?}", &s.icrc_cursors),
+                format!("USDRates: {:?}", &s.usd_rates),
                 format!("Modulation: {}", s.modulation,),
-                format!("LastBestStory: {}", s.last_best_story),
+                format!(
+                    "XDRRate: {:?}, last announced: {}",
+                    s.xdr_rates.back(),
+                    s.last_announced_xdr_rate
+                ),
+                format!(
+                    "LastBestStory: {} ({:?})",
+                    s.last_best_story, s.hackernews_mode
+                ),
                 format!("LastRSSTimestamps: {:?}", &s.last_rss_story_timestamp),
+                format!("TelegramCursors: {:?}", &s.telegram_cursors),
                 format!("SeenMessages={}", s.seen_messages.len(),),
+                format!("Labels: {}", s.labels.len()),
+                format!("MemoLabels: {}", s.memo_labels.len()),
+                format!(
+                    "LedgerStats: {} hours, latest: {:?}",
+                    s.ledger_stats.len(),
+                    s.ledger_stats.back()
+                ),
                 format!(
                     "Message Queue ({}): {:?}",
                     s.message_queue.len(),
-                    &s.message_queue
+                    s.message_queue
+                        .iter()
+                        .map(|m| (&m.body, &m.realm, m.blob_urls.len()))
+                        .collect::<Vec<_>>()
                 ),
             ]
         })
@@ -119,11 +316,16 @@ async fn daily_tasks() {
         }
     });
     log_if_error(modulation::go().await);
+    log_if_error(supply::go().await);
     log_if_error(hackernews::go().await);
+    netflow::go();
+    log_if_error(nnsmetrics::go().await);
 }
 
 async fn hourly_tasks() {
-    log_if_error(watcherguru::go().await);
+    for channel in telegram::channels() {
+        log_if_error(telegram::go(&channel).await);
+    }
     log_if_error(rss::go("BBC", "https://feeds.bbci.co.uk/news/world/rss.xml", "NEWS").await);
     log_if_error(
         rss::go(
@@ -133,25 +335,71 @@ async fn hourly_tasks() {
         )
         .await,
     );
+    let mut symbols = vec!["ICP"];
+    symbols.extend(icrcalert::LEDGERS.iter().map(|ledger| ledger.rate_symbol));
+    log_if_error(exchangerate::refresh(&symbols).await);
+    log_if_error(xdrrate::go().await);
     log_if_error(whalealert::go().await);
+    log_if_error(proposals::go().await);
+    log_if_error(proposals::check_votes().await);
+    log_if_error(proposals::check_outcomes().await);
+    log_if_error(sns::go().await);
+    for ledger in icrcalert::LEDGERS {
+        log_if_error(icrcalert::go(ledger).await);
+    }
 }
 
 async fn process_one_message() {
-    let Some((message, realm)) = mutate(|state| state.message_queue.pop_front()) else {
+    let Some(message) = mutate(|state| state.message_queue.pop_front()) else {
         return;
     };
-    if let Err(err) = send_message(&message, realm.clone()).await {
-        mutate(|state| {
-            state
-                .logs
-                .push_back(format!("Taggr response to message {}: {:?}", message, err));
-            state.message_queue.push_front((message, realm));
-        })
+    // If the parent was never posted, the reply is posted as a top-level post.
+    let parent = message.parent_post_id.or_else(|| {
+        message
+            .reply_to
+            .and_then(|id| read(|state| state.post_ids.get(&id).copied()))
+    });
+    let mut body = message.body.clone();
+    let mut blobs = Vec::new();
+    for (blob_id, url) in &message.blob_urls {
+        match telegram::fetch_photo(url).await {
+            Ok(bytes) => blobs.push((blob_id.clone(), bytes)),
+            // The post goes out without the blob rather than with a broken reference.
+            Err(err) => {
+                let reference = format!("(/blob/{})", blob_id);
+                body = body
+                    .lines()
+                    .filter(|line| !line.contains(&reference))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                mutate(|state| {
+                    state
+                        .logs
+                        .push_back(format!("Couldn't fetch the blob {}: {}", url, err))
+                });
+            }
+        }
+    }
+    match send_message(&body, message.realm.clone(), parent, blobs).await {
+        Ok(post_id) => mutate(|state| {
+            state.post_ids.insert(message.id, post_id);
+            while state.post_ids.len() > MAX_MSG_MEMORY {
+                state.post_ids.pop_first();
+            }
+        }),
+        Err(err) => mutate(|state| {
+            state.logs.push_back(format!(
+                "Taggr response to message {}: {:?}",
+                message.body, err
+            ));
+            state.message_queue.push_front(message);
+        }),
     }
 }
 
 #[ic_cdk_macros::init]
 fn init() {
+    mutate(labels::seed);
     set_timer();
 }
 
@@ -170,7 +418,7 @@ async fn fixture() {}
 
 #[ic_cdk_macros::pre_upgrade]
 fn pre_upgrade() {
-    let buffer: Vec<u8> = read(|s| bincode::serialize(s)).expect("couldn't serialize the state");
+    let buffer: Vec<u8> = read(snapshot::encode).expect("couldn't serialize the state");
     let writer = &mut stable::StableWriter::default();
     let _ = writer.write(&buffer);
 }
@@ -178,8 +426,9 @@ fn pre_upgrade() {
 #[ic_cdk_macros::post_upgrade]
 fn post_upgrade() {
     let bytes = stable::stable_bytes();
-    let state: State = bincode::deserialize(&bytes).unwrap();
+    let state: State = snapshot::decode(&bytes).expect("couldn't restore the state");
     STATE.with(|cell| cell.replace(state));
+    mutate(labels::rebuild);
     set_timer();
 }
 
@@ -188,3 +437,11 @@ fn log_if_error<T>(result: Result<T, String>) {
         mutate(|state| state.logs.push_back(format!("Error: {}", err)))
     }
 }
+
+fn controller_only() -> Result<(), String> {
+    if ic_cdk::api::is_controller(&ic_cdk::caller()) {
+        Ok(())
+    } else {
+        Err("only controllers can call this method".into())
+    }
+}
@@ -0,0 +1,153 @@
+use std::fmt;
+
+use super::{ledger::Transaction, State};
+
+// Memos of transfers to the cycles minting canister, see its interface.
+pub const MEMO_CREATE_CANISTER: u64 = 0x41455243; // CREA
+pub const MEMO_TOP_UP_CANISTER: u64 = 0x50555054; // TPUP
+pub const MEMO_MINT_CYCLES: u64 = 0x544e494d; // MINT
+
+// Byte memos longer than this are shortened when rendered.
+const MAX_HEX_BYTES: usize = 16;
+// Text memos longer than this are shortened when rendered.
+const MAX_TEXT_CHARS: usize = 64;
+
+/// A decoded transfer memo.
+#[derive(Debug, PartialEq)]
+pub enum Memo {
+    Number(u64),
+    Text(String),
+    /// A magic value marking a protocol, like a CMC top-up.
+    Protocol(&'static str),
+    Bytes(Vec<u8>),
+}
+
+impl fmt::Display for Memo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Memo::Number(n) => write!(f, "{}", n),
+            // Backticks would end the code span the memo is rendered in.
+            Memo::Text(text) => {
+                let text = text.replace('`', "'");
+                write!(
+                    f,
+                    "{}",
+                    text.chars().take(MAX_TEXT_CHARS).collect::<String>()
+                )?;
+                if text.chars().count() > MAX_TEXT_CHARS {
+                    write!(f, "…")?;
+                }
+                Ok(())
+            }
+            Memo::Protocol(name) => write!(f, "{}", name),
+            Memo::Bytes(bytes) => {
+                for byte in bytes.iter().take(MAX_HEX_BYTES) {
+                    write!(f, "{:02x}", byte)?;
+                }
+                if bytes.len() > MAX_HEX_BYTES {
+                    write!(f, "…")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Returns the numeric memo of the transaction, reading ICRC-1 memos of 8 bytes as
+/// little-endian numbers.
+pub fn numeric(transaction: &Transaction) -> u64 {
+    match transaction.icrc1_memo.as_deref() {
+        Some(bytes) if bytes.len() == 8 => {
+            let mut memo = [0; 8];
+            memo.copy_from_slice(bytes);
+            u64::from_le_bytes(memo)
+        }
+        _ => transaction.memo.0,
+    }
+}
+
+/// Decodes a numeric memo; zero means no memo.
+pub fn decode_number(memo: u64) -> Option<Memo> {
+    Some(match memo {
+        0 => return None,
+        MEMO_TOP_UP_CANISTER => Memo::Protocol("CMC canister top-up"),
+        MEMO_CREATE_CANISTER => Memo::Protocol("CMC canister creation"),
+        MEMO_MINT_CYCLES => Memo::Protocol("CMC cycles mint"),
+        n => Memo::Number(n),
+    })
+}
+
+/// Decodes a byte memo as text if it's printable UTF-8, as a number if it has 8 bytes and as raw
+/// bytes otherwise.
+pub fn decode_bytes(bytes: &[u8]) -> Option<Memo> {
+    if bytes.iter().all(|byte| *byte == 0) {
+        return None;
+    }
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        let text = text.trim();
+        if !text.is_empty() && !text.chars().any(char::is_control) {
+            return Some(Memo::Text(text.to_string()));
+        }
+    }
+    if bytes.len() == 8 {
+        let mut memo = [0; 8];
+        memo.copy_from_slice(bytes);
+        return decode_number(u64::from_le_bytes(memo));
+    }
+    Some(Memo::Bytes(bytes.to_vec()))
+}
+
+/// Decodes the memo of an ICP transaction, preferring the ICRC-1 memo if set.
+pub fn decode(transaction: &Transaction) -> Option<Memo> {
+    match transaction.icrc1_memo.as_deref() {
+        Some(bytes) => decode_bytes(bytes),
+        None => decode_number(transaction.memo.0),
+    }
+}
+
+/// Renders the memo as ` with memo `…``, followed by its label if one is registered. Unlabeled
+/// text memos are user-controlled, so they're only rendered if `show_text` is set.
+pub fn render(state: &State, memo: &Memo, show_text: bool) -> String {
+    let label = state.memo_labels.get(&memo.to_string());
+    match (memo, label) {
+        (_, Some(label)) => format!(" with memo `{}` ({})", memo, label),
+        (Memo::Text(_), None) if !show_text => String::default(),
+        _ => format!(" with memo `{}`", memo),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_bytes, decode_number, Memo, MAX_TEXT_CHARS, MEMO_TOP_UP_CANISTER};
+
+    #[test]
+    fn test_decoding() {
+        assert_eq!(decode_number(0), None);
+        assert_eq!(decode_number(42), Some(Memo::Number(42)));
+        assert_eq!(
+            decode_number(MEMO_TOP_UP_CANISTER),
+            Some(Memo::Protocol("CMC canister top-up"))
+        );
+        assert_eq!(decode_bytes(&[0; 32]), None);
+        assert_eq!(
+            decode_bytes(b"deposit 17"),
+            Some(Memo::Text("deposit 17".into()))
+        );
+        assert_eq!(
+            decode_bytes(&1234_u64.to_le_bytes()),
+            Some(Memo::Number(1234))
+        );
+        assert_eq!(
+            decode_bytes(&MEMO_TOP_UP_CANISTER.to_le_bytes()),
+            Some(Memo::Protocol("CMC canister top-up"))
+        );
+        let bytes = decode_bytes(&[0xff; 20]).unwrap();
+        assert_eq!(bytes.to_string(), format!("{}…", "ff".repeat(16)));
+        assert_eq!(
+            Memo::Text("`[pump](https://x)` now".into()).to_string(),
+            "'[pump](https://x)' now"
+        );
+        let long = Memo::Text("a".repeat(100)).to_string();
+        assert_eq!(long, format!("{}…", "a".repeat(MAX_TEXT_CHARS)));
+    }
+}
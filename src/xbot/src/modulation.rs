@@ -1,8 +1,54 @@
+use std::collections::VecDeque;
+
 use crate::{mutate, read};
 
 use super::schedule_message;
 use ic_ledger_types::MAINNET_CYCLES_MINTING_CANISTER_ID;
 
+// Samples kept in the state (two years of daily samples).
+const MAX_SAMPLES: usize = 2 * 365;
+const WEEK_SECS: u64 = 7 * 24 * 60 * 60;
+
+/// Returns the change of the modulation since the latest sample at least a week old.
+fn weekly_change(history: &VecDeque<(u64, i32)>, now: u64, modulation: i32) -> Option<i32> {
+    history
+        .iter()
+        .rev()
+        .find(|(timestamp, _)| timestamp + WEEK_SECS <= now)
+        .map(|(_, previous)| modulation - previous)
+}
+
+fn trend(change: Option<i32>) -> String {
+    change
+        .map(|change| format!(" (7-day change: `{:+.2}`)", change as f32 / 100.0))
+        .unwrap_or_default()
+}
+
+fn message(modulation: i32, new_modulation: i32, change: Option<i32>) -> Option<String> {
+    if new_modulation > 0
+        && new_modulation > modulation
+        && (modulation <= 0 || new_modulation / 100 > modulation / 100)
+    {
+        let rockets = (0..new_modulation / 100)
+            .map(|_| "🚀".to_string())
+            .collect::<Vec<_>>()
+            .join("");
+        Some(format!(
+            "📈 The neuron maturity #modulation is now `{}` {}{}",
+            100.0 + (new_modulation as f32 / 100.0),
+            rockets,
+            trend(change)
+        ))
+    } else if new_modulation < 0 && modulation >= 0 {
+        Some(format!(
+            "📉 The neuron maturity #modulation is now below `100`{}",
+            trend(change)
+        ))
+    } else {
+        None
+    }
+}
+
 pub async fn go() -> Result<(), String> {
     let modulation = read(|s| s.modulation);
     let (response,): (Result<i32, String>,) = ic_cdk::call(
@@ -12,31 +58,59 @@ pub async fn go() -> Result<(), String> {
     )
     .await
     .map_err(|err| format!("couldn't call cmc: {:?}", err))?;
-    let new_modulation = response.expect("couldn't get the modulation");
+    let new_modulation = response.map_err(|err| format!("couldn't get the modulation: {}", err))?;
+    let now = ic_cdk::api::time() / 1_000_000_000;
     mutate(|state| {
+        let change = weekly_change(&state.modulation_history, now, new_modulation);
         state.modulation = new_modulation;
-        state
-            .logs
-            .push_back(format!("Modulation: {} -> {}", modulation, new_modulation));
+        state.modulation_history.push_back((now, new_modulation));
+        while state.modulation_history.len() > MAX_SAMPLES {
+            state.modulation_history.pop_front();
+        }
+        state.logs.push_back(format!(
+            "Modulation: {} -> {}{}",
+            modulation,
+            new_modulation,
+            trend(change)
+        ));
+        if let Some(message) = message(modulation, new_modulation, change) {
+            schedule_message(state, message, None);
+        }
     });
-    let message = if new_modulation > 0
-        && new_modulation > modulation
-        && (modulation <= 0 || new_modulation / 100 > modulation / 100)
-    {
-        let rockets = (0..new_modulation / 100)
-            .map(|_| "🚀".to_string())
-            .collect::<Vec<_>>()
-            .join("");
-        format!(
-            "📈 The neuron maturity #modulation is now `{}` {}",
-            100.0 + (new_modulation as f32 / 100.0),
-            rockets
-        )
-    } else if new_modulation < 0 && modulation >= 0 {
-        "📉 The neuron maturity #modulation is now below `100` ".to_owned()
-    } else {
-        return Ok(());
-    };
-    mutate(|s| schedule_message(s, message, None));
     Ok(())
 }
+
+/// Returns the recorded modulation as (timestamp in seconds, basis points).
+#[ic_cdk_macros::query]
+fn modulation_history() -> Vec<(u64, i32)> {
+    read(|s| s.modulation_history.iter().copied().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{message, weekly_change, WEEK_SECS};
+    use std::collections::VecDeque;
+
+    #[test]
+    fn test_trend() {
+        let day = 24 * 60 * 60;
+        let history = (0..10)
+            .map(|i| (i * day, 100 + i as i32 * 10))
+            .collect::<VecDeque<_>>();
+        assert_eq!(weekly_change(&history, 9 * day, 200), Some(80));
+        assert_eq!(weekly_change(&history, WEEK_SECS - 1, 200), None);
+        assert_eq!(
+            message(180, 205, Some(80)).unwrap(),
+            "📈 The neuron maturity #modulation is now `102.05` 🚀🚀 (7-day change: `+0.80`)"
+        );
+        assert_eq!(message(205, 210, Some(5)), None);
+        assert_eq!(
+            message(20, -10, Some(-30)).unwrap(),
+            "📉 The neuron maturity #modulation is now below `100` (7-day change: `-0.30`)"
+        );
+        assert_eq!(
+            message(20, -10, None).unwrap(),
+            "📉 The neuron maturity #modulation is now below `100`"
+        );
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::BTreeMap;
+
+use ic_ledger_types::{AccountIdentifier, Tokens};
+use num_format::{Locale, ToFormattedString};
+
+use super::{
+    labels::{self, Category},
+    ledger::{Block, Operation},
+    mutate, schedule_message, State,
+};
+
+/// Returns the name of the exchange owning the account, if any.
+fn exchange(state: &State, account: &AccountIdentifier) -> Option<String> {
+    labels::find(state, &account.to_string())
+        .filter(|label| label.category == Category::Exchange)
+        .map(|label| label.name)
+}
+
+/// Accounts the transfer of the block to the in- and outflows of the exchanges involved.
+pub fn record(state: &mut State, block: &Block) {
+    let Some(Operation::Transfer {
+        from, to, amount, ..
+    }) = block.transaction.operation.as_ref()
+    else {
+        return;
+    };
+    let (source, target) = (exchange(state, from), exchange(state, to));
+    // Movements between the wallets of the same exchange are no flows.
+    if source == target {
+        return;
+    }
+    if let Some(name) = target {
+        state.exchange_flows.entry(name).or_default().0 += amount.e8s();
+    }
+    if let Some(name) = source {
+        state.exchange_flows.entry(name).or_default().1 += amount.e8s();
+    }
+}
+
+fn signed_icp(e8s: i64) -> String {
+    let sign = if e8s > 0 { "+" } else { "" };
+    format!(
+        "{}{}",
+        sign,
+        (e8s / Tokens::SUBDIVIDABLE_BY as i64).to_formatted_string(&Locale::de_CH)
+    )
+}
+
+fn icp(e8s: u64) -> String {
+    (e8s / Tokens::SUBDIVIDABLE_BY).to_formatted_string(&Locale::de_CH)
+}
+
+/// Renders the report of the week's flows and returns it with the net flow per exchange.
+fn report(
+    flows: &BTreeMap<String, (u64, u64)>,
+    previous: &BTreeMap<String, i64>,
+) -> (String, BTreeMap<String, i64>) {
+    let net_flows: BTreeMap<String, i64> = flows
+        .iter()
+        .map(|(name, (inflow, outflow))| (name.clone(), *inflow as i64 - *outflow as i64))
+        .collect();
+    let mut lines = flows
+        .iter()
+        .map(|(name, (inflow, outflow))| {
+            let net = net_flows[name];
+            let change = previous
+                .get(name)
+                .map(|last| format!(", `{}` vs. last week", signed_icp(net - last)))
+                .unwrap_or_default();
+            format!(
+                "- {}: `{}` ICP (in `{}`, out `{}`){}",
+                name,
+                signed_icp(net),
+                icp(*inflow),
+                icp(*outflow),
+                change
+            )
+        })
+        .collect::<Vec<_>>();
+    let total: i64 = net_flows.values().sum();
+    let change = if previous.is_empty() {
+        String::default()
+    } else {
+        format!(
+            ", `{}` vs. last week",
+            signed_icp(total - previous.values().sum::<i64>())
+        )
+    };
+    lines.push(format!(
+        "\n**Total net flow**: `{}` ICP{}",
+        signed_icp(total),
+        change
+    ));
+    let msg = format!(
+        "📊 #ExchangeFlows of the week\n\nNet ICP flows into (+) and out of (-) exchanges:\n\n{}",
+        lines.join("\n")
+    );
+    (msg, net_flows)
+}
+
+const WEEK_SECS: u64 = 7 * 24 * 60 * 60;
+
+/// Posts the weekly report and starts a new week, if a week passed since the last report.
+pub fn go() {
+    let now = ic_cdk::api::time() / 1_000_000_000;
+    mutate(|s| {
+        // The first week starts with the first check.
+        if s.last_netflow_report == 0 {
+            s.last_netflow_report = now;
+        }
+        if now < s.last_netflow_report + WEEK_SECS {
+            return;
+        }
+        s.last_netflow_report = now;
+        if s.exchange_flows.is_empty() {
+            return;
+        }
+        let (msg, net_flows) = report(&s.exchange_flows, &s.last_exchange_net_flows);
+        schedule_message(s, msg, Some("ICP".into()));
+        s.exchange_flows.clear();
+        s.last_exchange_net_flows = net_flows;
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::report;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_report() {
+        let e8s = 100_000_000;
+        let flows = BTreeMap::from([
+            ("Binance".to_string(), (5_000 * e8s, 3_000 * e8s)),
+            ("Coinbase".to_string(), (1_000 * e8s, 4_000 * e8s)),
+        ]);
+        let previous = BTreeMap::from([("Binance".to_string(), 500 * e8s as i64)]);
+        let (msg, net_flows) = report(&flows, &previous);
+        assert_eq!(net_flows["Coinbase"], -3_000 * e8s as i64);
+        assert!(msg
+            .contains("- Binance: `+2’000` ICP (in `5’000`, out `3’000`), `+1’500` vs. last week"));
+        assert!(msg.contains("- Coinbase: `-3’000` ICP (in `1’000`, out `4’000`)\n"));
+        assert!(msg.contains("**Total net flow**: `-1’000` ICP, `-1’500` vs. last week"));
+    }
+}
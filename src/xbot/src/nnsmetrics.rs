@@ -0,0 +1,172 @@
+use crate::{mutate, read, State};
+
+use super::{proposals, schedule_message};
+use candid::CandidType;
+use ic_ledger_types::{Tokens, MAINNET_GOVERNANCE_CANISTER_ID};
+use num_format::{Locale, ToFormattedString};
+use serde::{Deserialize, Serialize};
+
+const REALM: &str = "ICP";
+// Weekly metrics kept in the state (two years).
+const MAX_METRICS: usize = 104;
+const WEEK_SECS: u64 = 7 * 24 * 60 * 60;
+
+// Only the fields used by the bot.
+#[derive(CandidType, Deserialize)]
+struct GovernanceCachedMetrics {
+    timestamp_seconds: u64,
+    total_staked_e8s: u64,
+    dissolving_neurons_count: u64,
+    not_dissolving_neurons_count: u64,
+    dissolved_neurons_count: u64,
+    total_maturity_e8s_equivalent: u64,
+    total_staked_maturity_e8s_equivalent: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+struct GovernanceError {
+    error_message: String,
+}
+
+/// A weekly snapshot of the NNS governance metrics.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Metrics {
+    pub timestamp: u64,
+    pub total_staked_e8s: u64,
+    pub dissolving_neurons: u64,
+    pub not_dissolving_neurons: u64,
+    pub dissolved_neurons: u64,
+    /// The total voting power of the latest proposal's tally.
+    pub voting_power: u64,
+    pub total_maturity_e8s: u64,
+    pub staked_maturity_e8s: u64,
+}
+
+async fn fetch() -> Result<Metrics, String> {
+    let (response,): (Result<GovernanceCachedMetrics, GovernanceError>,) =
+        ic_cdk::call(MAINNET_GOVERNANCE_CANISTER_ID, "get_metrics", ())
+            .await
+            .map_err(|err| format!("couldn't get governance metrics: {:?}", err))?;
+    let metrics = response
+        .map_err(|err| format!("couldn't get governance metrics: {}", err.error_message))?;
+    // The governance metrics don't contain the voting power, but every tally does.
+    let voting_power = proposals::list_proposals(None)
+        .await?
+        .into_iter()
+        .find_map(|info| info.latest_tally)
+        .map(|tally| tally.total)
+        .unwrap_or_default();
+    Ok(Metrics {
+        timestamp: metrics.timestamp_seconds,
+        total_staked_e8s: metrics.total_staked_e8s,
+        dissolving_neurons: metrics.dissolving_neurons_count,
+        not_dissolving_neurons: metrics.not_dissolving_neurons_count,
+        dissolved_neurons: metrics.dissolved_neurons_count,
+        voting_power,
+        total_maturity_e8s: metrics.total_maturity_e8s_equivalent,
+        staked_maturity_e8s: metrics.total_staked_maturity_e8s_equivalent,
+    })
+}
+
+/// A named metric with its renderer.
+type Metric = (&'static str, fn(&Metrics) -> u64, fn(u64) -> String);
+
+fn icp(e8s: u64) -> String {
+    format!(
+        "`{}` ICP",
+        (e8s / Tokens::SUBDIVIDABLE_BY).to_formatted_string(&Locale::de_CH)
+    )
+}
+
+fn count(n: u64) -> String {
+    format!("`{}`", n.to_formatted_string(&Locale::de_CH))
+}
+
+fn change(current: u64, previous: Option<u64>) -> String {
+    match previous {
+        Some(previous) if previous > 0 => format!(
+            " ({:+.2}%)",
+            (current as f64 - previous as f64) * 100.0 / previous as f64
+        ),
+        _ => String::default(),
+    }
+}
+
+/// Renders the metrics with their change since the previous snapshot.
+fn report(current: &Metrics, previous: Option<&Metrics>) -> String {
+    let lines: [Metric; 7] = [
+        ("Total staked", |m| m.total_staked_e8s, icp),
+        ("Voting power", |m| m.voting_power, icp),
+        (
+            "Non-dissolving neurons",
+            |m| m.not_dissolving_neurons,
+            count,
+        ),
+        ("Dissolving neurons", |m| m.dissolving_neurons, count),
+        ("Dissolved neurons", |m| m.dissolved_neurons, count),
+        ("Total maturity", |m| m.total_maturity_e8s, icp),
+        ("Staked maturity", |m| m.staked_maturity_e8s, icp),
+    ];
+    let lines = lines
+        .iter()
+        .map(|(name, metric, render)| {
+            format!(
+                "- {}: {}{}",
+                name,
+                render(metric(current)),
+                change(metric(current), previous.map(metric))
+            )
+        })
+        .collect::<Vec<_>>();
+    format!(
+        "🏛️ #NNS governance metrics of the week\n\n{}",
+        lines.join("\n")
+    )
+}
+
+/// Once a week, fetches the current metrics, posts them with their weekly change and adds them
+/// to the time series.
+pub async fn go() -> Result<(), String> {
+    let now = ic_cdk::api::time() / 1_000_000_000;
+    if now < read(|s| s.last_metrics_report) + WEEK_SECS {
+        return Ok(());
+    }
+    let metrics = fetch().await?;
+    mutate(|s: &mut State| {
+        s.last_metrics_report = now;
+        let msg = report(&metrics, s.governance_metrics.back());
+        schedule_message(s, msg, Some(REALM.into()));
+        s.governance_metrics.push_back(metrics);
+        while s.governance_metrics.len() > MAX_METRICS {
+            s.governance_metrics.pop_front();
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{report, Metrics};
+
+    #[test]
+    fn test_report() {
+        let e8s = 100_000_000;
+        let previous = Metrics {
+            total_staked_e8s: 200_000_000 * e8s,
+            not_dissolving_neurons: 40_000,
+            voting_power: 0,
+            ..Default::default()
+        };
+        let current = Metrics {
+            total_staked_e8s: 202_000_000 * e8s,
+            not_dissolving_neurons: 39_000,
+            voting_power: 400_000_000 * e8s,
+            ..Default::default()
+        };
+        let msg = report(&current, Some(&previous));
+        assert!(msg.contains("- Total staked: `202’000’000` ICP (+1.00%)\n"));
+        assert!(msg.contains("- Voting power: `400’000’000` ICP\n"));
+        assert!(msg.contains("- Non-dissolving neurons: `39’000` (-2.50%)\n"));
+        assert!(!report(&current, None).contains('%'));
+    }
+}
@@ -0,0 +1,639 @@
+use crate::{controller_only, mutate, read, State};
+
+use super::{schedule_message, schedule_reply, schedule_reply_to_post};
+use candid::CandidType;
+use ic_ledger_types::{Tokens, MAINNET_GOVERNANCE_CANISTER_ID};
+use num_format::{Locale, ToFormattedString};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, convert::TryFrom};
+
+const REALM: &str = "ICP";
+const PAGE_SIZE: u32 = 50;
+const MAX_PAGES: usize = 10;
+const MAX_EXCERPT_LEN: usize = 400;
+
+// Topics of the NNS governance, indexed by their numeric value.
+const TOPICS: &[&str] = &[
+    "Unspecified",
+    "NeuronManagement",
+    "ExchangeRate",
+    "NetworkEconomics",
+    "Governance",
+    "NodeAdmin",
+    "ParticipantManagement",
+    "SubnetManagement",
+    "NetworkCanisterManagement",
+    "Kyc",
+    "NodeProviderRewards",
+    "SnsDecentralizationSale",
+    "IcOsVersionDeployment",
+    "IcOsVersionElection",
+    "SnsAndCommunityFund",
+    "ApiBoundaryNodeManagement",
+    "SubnetRental",
+    "ProtocolCanisterManagement",
+    "ServiceNervousSystemManagement",
+];
+
+const VOTE_YES: i32 = 1;
+const VOTE_NO: i32 = 2;
+
+const STATUS_REJECTED: i32 = 2;
+const STATUS_EXECUTED: i32 = 4;
+const STATUS_FAILED: i32 = 5;
+
+// Routine topics not posted unless included explicitly.
+const EXCLUDED_TOPICS: &[&str] = &[
+    "NeuronManagement",
+    "ExchangeRate",
+    "NodeAdmin",
+    "ParticipantManagement",
+    "NodeProviderRewards",
+    "IcOsVersionDeployment",
+    "ApiBoundaryNodeManagement",
+];
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug)]
+pub struct NeuronId {
+    pub id: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug)]
+pub struct ProposalId {
+    pub id: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+struct ListProposalInfo {
+    include_reward_status: Vec<i32>,
+    omit_large_fields: Option<bool>,
+    before_proposal: Option<ProposalId>,
+    limit: u32,
+    exclude_topic: Vec<i32>,
+    include_all_manage_neuron_proposals: Option<bool>,
+    include_status: Vec<i32>,
+}
+
+// Only the fields used by the bot; the action of the proposal is omitted.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Proposal {
+    pub title: Option<String>,
+    pub summary: String,
+    pub url: String,
+}
+
+/// Voting power in e8s.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Tally {
+    pub yes: u64,
+    pub no: u64,
+    pub total: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ProposalInfo {
+    pub id: Option<ProposalId>,
+    pub status: i32,
+    pub topic: i32,
+    pub proposer: Option<NeuronId>,
+    pub proposal: Option<Proposal>,
+    pub latest_tally: Option<Tally>,
+}
+
+/// A proposal posted by the bot whose outcome is still pending.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct OpenProposal {
+    /// The id of the scheduled message announcing the proposal.
+    pub message_id: u64,
+    /// The Taggr post id of the announcement, once posted.
+    pub post_id: Option<u64>,
+    /// Votes of known neurons already reported.
+    pub votes: BTreeMap<u64, i32>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct BallotInfo {
+    vote: i32,
+    proposal_id: Option<ProposalId>,
+}
+
+// Only the fields used by the bot.
+#[derive(CandidType, Deserialize)]
+struct NeuronInfo {
+    recent_ballots: Vec<BallotInfo>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct GovernanceError {
+    error_message: String,
+}
+
+#[derive(CandidType, Deserialize)]
+struct KnownNeuronData {
+    name: String,
+}
+
+#[derive(CandidType, Deserialize)]
+struct KnownNeuron {
+    id: Option<NeuronId>,
+    known_neuron_data: Option<KnownNeuronData>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct ListKnownNeuronsResponse {
+    known_neurons: Vec<KnownNeuron>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct ListProposalInfoResponse {
+    proposal_info: Vec<ProposalInfo>,
+}
+
+pub fn topic_name(topic: i32) -> &'static str {
+    usize::try_from(topic)
+        .ok()
+        .and_then(|index| TOPICS.get(index))
+        .copied()
+        .unwrap_or("Unspecified")
+}
+
+fn parse_topic(name: &str) -> Result<i32, String> {
+    TOPICS
+        .iter()
+        .position(|topic| topic.eq_ignore_ascii_case(name.trim()))
+        .map(|index| index as i32)
+        .ok_or_else(|| {
+            format!(
+                "unknown topic {}, expected one of {}",
+                name,
+                TOPICS.join(", ")
+            )
+        })
+}
+
+/// Checks the topic against the configured filters, falling back to the default exclusions.
+fn included(state: &State, topic: i32) -> bool {
+    state
+        .proposal_topics
+        .get(&topic)
+        .copied()
+        .unwrap_or_else(|| !EXCLUDED_TOPICS.contains(&topic_name(topic)))
+}
+
+/// Returns the beginning of the summary, cut at a line or word boundary.
+pub fn excerpt(summary: &str, max_len: usize) -> String {
+    let summary = summary.trim();
+    if summary.len() <= max_len {
+        return summary.to_string();
+    }
+    let mut end = max_len;
+    while !summary.is_char_boundary(end) {
+        end -= 1;
+    }
+    let cut = &summary[..end];
+    let boundary = |c| cut.rfind(c).filter(|index| *index > max_len / 2);
+    let cut = match boundary('\n').or_else(|| boundary(' ')) {
+        Some(index) => &cut[..index],
+        None => cut,
+    };
+    format!("{}…", cut.trim_end())
+}
+
+fn message(info: &ProposalInfo) -> Option<String> {
+    let id = info.id?.id;
+    let proposal = info.proposal.as_ref()?;
+    let proposer = info
+        .proposer
+        .map(|neuron| {
+            format!(
+                "[{}](https://dashboard.internetcomputer.org/neuron/{})",
+                neuron.id, neuron.id
+            )
+        })
+        .unwrap_or_else(|| "unknown".into());
+    let summary = excerpt(&proposal.summary, MAX_EXCERPT_LEN)
+        .lines()
+        .map(|line| format!("> {}", line))
+        .collect::<Vec<_>>()
+        .join("\n");
+    Some(format!(
+        "🗳️ #NNS proposal [{}](https://dashboard.internetcomputer.org/proposal/{}): **{}**\n\nTopic: `{}`, proposer: {}{}\n\n{}",
+        id,
+        id,
+        proposal.title.as_deref().unwrap_or("Untitled"),
+        topic_name(info.topic),
+        proposer,
+        if proposal.url.is_empty() {
+            String::default()
+        } else {
+            format!(", [discussion]({})", proposal.url)
+        },
+        summary
+    ))
+}
+
+fn percent(part: u64, total: u64) -> String {
+    format!("{:.1}%", part as f64 * 100.0 / total.max(1) as f64)
+}
+
+fn voting_power(e8s: u64) -> String {
+    (e8s / Tokens::SUBDIVIDABLE_BY).to_formatted_string(&Locale::de_CH)
+}
+
+/// Renders the outcome of a decided proposal, or nothing if it's still open or awaits execution.
+fn outcome(info: &ProposalInfo) -> Option<String> {
+    let id = info.id?.id;
+    let verdict = match info.status {
+        STATUS_EXECUTED => "✅ adopted and executed",
+        STATUS_REJECTED => "❌ rejected",
+        STATUS_FAILED => "⚠️ adopted, but its execution failed",
+        _ => return None,
+    };
+    let tally = info
+        .latest_tally
+        .as_ref()
+        .map(|tally| {
+            let cast = tally.yes + tally.no;
+            format!(
+                "\n\nYes: `{}` ({}), no: `{}` ({}), turnout: `{}`",
+                voting_power(tally.yes),
+                percent(tally.yes, cast),
+                voting_power(tally.no),
+                percent(tally.no, cast),
+                percent(cast, tally.total)
+            )
+        })
+        .unwrap_or_default();
+    Some(format!(
+        "Proposal [{}](https://dashboard.internetcomputer.org/proposal/{}) was {}.{}",
+        id, id, verdict, tally
+    ))
+}
+
+pub async fn list_proposals(before: Option<u64>) -> Result<Vec<ProposalInfo>, String> {
+    let args = ListProposalInfo {
+        include_reward_status: Default::default(),
+        omit_large_fields: Some(true),
+        before_proposal: before.map(|id| ProposalId { id }),
+        limit: PAGE_SIZE,
+        exclude_topic: Default::default(),
+        include_all_manage_neuron_proposals: Some(false),
+        include_status: Default::default(),
+    };
+    let (response,): (ListProposalInfoResponse,) =
+        ic_cdk::call(MAINNET_GOVERNANCE_CANISTER_ID, "list_proposals", (args,))
+            .await
+            .map_err(|err| format!("couldn't list proposals: {:?}", err))?;
+    Ok(response.proposal_info)
+}
+
+pub async fn go() -> Result<(), String> {
+    let last_proposal = read(|s| s.last_proposal);
+    let mut proposals = Vec::new();
+    let mut before = None;
+    // Proposals are listed newest first, so we page back until we reach the last seen one.
+    for _ in 0..MAX_PAGES {
+        let page = list_proposals(before).await?;
+        let done = page.len() < PAGE_SIZE as usize;
+        for info in page {
+            let Some(id) = info.id.map(|id| id.id) else {
+                continue;
+            };
+            before = Some(before.unwrap_or(id).min(id));
+            if id > last_proposal {
+                proposals.push((id, info));
+            }
+        }
+        // On the very first run, we only remember the latest proposal.
+        if done || last_proposal == 0 || before.unwrap_or_default() <= last_proposal {
+            break;
+        }
+    }
+    proposals.sort_by_key(|(id, _)| *id);
+
+    mutate(|s| {
+        let Some(latest) = proposals.last().map(|(id, _)| *id) else {
+            return;
+        };
+        if last_proposal > 0 {
+            for (_, info) in &proposals {
+                if !included(s, info.topic) {
+                    continue;
+                }
+                let Some(msg) = message(info) else {
+                    continue;
+                };
+                if let (Some(message_id), Some(id)) =
+                    (schedule_message(s, msg, Some(REALM.into())), info.id)
+                {
+                    s.open_proposals.insert(
+                        id.id,
+                        OpenProposal {
+                            message_id,
+                            post_id: None,
+                            votes: Default::default(),
+                        },
+                    );
+                }
+            }
+        }
+        s.last_proposal = latest;
+        s.logs.push_back(format!(
+            "New proposals: {} (latest: {})",
+            proposals.len(),
+            latest
+        ));
+    });
+    Ok(())
+}
+
+async fn get_proposal_info(id: u64) -> Result<Option<ProposalInfo>, String> {
+    let (response,): (Option<ProposalInfo>,) =
+        ic_cdk::call(MAINNET_GOVERNANCE_CANISTER_ID, "get_proposal_info", (id,))
+            .await
+            .map_err(|err| format!("couldn't get proposal {}: {:?}", id, err))?;
+    Ok(response)
+}
+
+/// Remembers the post ids of the announcements, as message ids are only mapped to post ids for
+/// a limited number of recent messages.
+fn remember_post_ids(state: &mut State) {
+    for open in state.open_proposals.values_mut() {
+        if open.post_id.is_none() {
+            open.post_id = state.post_ids.get(&open.message_id).copied();
+        }
+    }
+}
+
+/// Schedules a reply to the announcement of the open proposal.
+fn reply(state: &mut State, id: u64, msg: String) {
+    let Some(open) = state.open_proposals.get(&id) else {
+        return;
+    };
+    match open.post_id {
+        Some(post_id) => schedule_reply_to_post(state, post_id, msg, Some(REALM.into())),
+        None => schedule_reply(state, open.message_id, msg, Some(REALM.into())),
+    };
+}
+
+/// Polls the status of all open proposals posted by the bot and replies to their posts once
+/// they're decided.
+pub async fn check_outcomes() -> Result<(), String> {
+    mutate(remember_post_ids);
+    let open = read(|s| s.open_proposals.keys().copied().collect::<Vec<_>>());
+    for id in open {
+        // A failing proposal shouldn't block the checks of the others.
+        let info = match get_proposal_info(id).await {
+            Ok(info) => info,
+            Err(err) => {
+                mutate(|s| {
+                    s.logs
+                        .push_back(format!("Error: couldn't check proposal {}: {}", id, err))
+                });
+                continue;
+            }
+        };
+        mutate(|s| {
+            let Some(info) = info else {
+                s.open_proposals.remove(&id);
+                return;
+            };
+            if let Some(msg) = outcome(&info) {
+                reply(s, id, msg);
+                s.open_proposals.remove(&id);
+            }
+        });
+    }
+    Ok(())
+}
+
+async fn get_neuron_info(id: u64) -> Result<NeuronInfo, String> {
+    let (response,): (Result<NeuronInfo, GovernanceError>,) =
+        ic_cdk::call(MAINNET_GOVERNANCE_CANISTER_ID, "get_neuron_info", (id,))
+            .await
+            .map_err(|err| format!("couldn't get neuron {}: {:?}", id, err))?;
+    response.map_err(|err| format!("couldn't get neuron {}: {}", id, err.error_message))
+}
+
+fn vote_name(vote: i32) -> &'static str {
+    match vote {
+        VOTE_YES => "✅ yes",
+        VOTE_NO => "❌ no",
+        _ => "unspecified",
+    }
+}
+
+fn votes_message(id: u64, votes: &[(&str, i32)]) -> String {
+    format!(
+        "Votes of known neurons on proposal {}:\n\n{}",
+        id,
+        votes
+            .iter()
+            .map(|(name, vote)| format!("- {}: {}", name, vote_name(*vote)))
+            .collect::<Vec<_>>()
+            .join("\n")
+    )
+}
+
+/// Reads the recent ballots of the known neurons and replies to the announcements of open
+/// proposals with the votes cast since the last check.
+pub async fn check_votes() -> Result<(), String> {
+    let neurons = read(|s| s.known_neurons.keys().copied().collect::<Vec<_>>());
+    if neurons.is_empty() || read(|s| s.open_proposals.is_empty()) {
+        return Ok(());
+    }
+    mutate(remember_post_ids);
+    // Proposal id -> (neuron id, vote)
+    let mut ballots: BTreeMap<u64, Vec<(u64, i32)>> = BTreeMap::new();
+    for neuron in neurons {
+        let info = match get_neuron_info(neuron).await {
+            Ok(info) => info,
+            Err(err) => {
+                mutate(|s| s.logs.push_back(format!("Error: {}", err)));
+                continue;
+            }
+        };
+        for ballot in info.recent_ballots {
+            if let (Some(proposal), VOTE_YES | VOTE_NO) = (ballot.proposal_id, ballot.vote) {
+                ballots
+                    .entry(proposal.id)
+                    .or_default()
+                    .push((neuron, ballot.vote));
+            }
+        }
+    }
+    mutate(|s| {
+        let open = s.open_proposals.keys().copied().collect::<Vec<_>>();
+        for id in open {
+            let Some(ballots) = ballots.get(&id) else {
+                continue;
+            };
+            let new_votes = ballots
+                .iter()
+                .filter(|(neuron, _)| !s.open_proposals[&id].votes.contains_key(neuron))
+                .copied()
+                .collect::<Vec<_>>();
+            if new_votes.is_empty() {
+                continue;
+            }
+            let named = new_votes
+                .iter()
+                .map(|(neuron, vote)| {
+                    let name = s.known_neurons.get(neuron).map(String::as_str);
+                    (name.unwrap_or("unknown neuron"), *vote)
+                })
+                .collect::<Vec<_>>();
+            let msg = votes_message(id, &named);
+            if let Some(open) = s.open_proposals.get_mut(&id) {
+                open.votes.extend(new_votes);
+            }
+            reply(s, id, msg);
+        }
+    });
+    Ok(())
+}
+
+/// Registers a known neuron whose votes are reported on posted proposals.
+#[ic_cdk_macros::update]
+fn add_known_neuron(id: u64, name: String) -> Result<(), String> {
+    controller_only()?;
+    if name.trim().is_empty() {
+        return Err("name must not be empty".into());
+    }
+    mutate(|s| s.known_neurons.insert(id, name.trim().to_string()));
+    Ok(())
+}
+
+#[ic_cdk_macros::update]
+fn remove_known_neuron(id: u64) -> Result<(), String> {
+    controller_only()?;
+    mutate(|s| s.known_neurons.remove(&id))
+        .map(|_| ())
+        .ok_or("no such neuron".into())
+}
+
+/// Registers all known neurons listed by the governance canister and returns their number.
+#[ic_cdk_macros::update]
+async fn import_known_neurons() -> Result<u32, String> {
+    controller_only()?;
+    let (response,): (ListKnownNeuronsResponse,) =
+        ic_cdk::call(MAINNET_GOVERNANCE_CANISTER_ID, "list_known_neurons", ())
+            .await
+            .map_err(|err| format!("couldn't list known neurons: {:?}", err))?;
+    let neurons = response
+        .known_neurons
+        .into_iter()
+        .filter_map(|neuron| Some((neuron.id?.id, neuron.known_neuron_data?.name)))
+        .collect::<Vec<_>>();
+    let count = neurons.len() as u32;
+    mutate(|s| s.known_neurons.extend(neurons));
+    Ok(count)
+}
+
+#[ic_cdk_macros::query]
+fn list_known_neurons() -> Vec<(u64, String)> {
+    read(|s| {
+        s.known_neurons
+            .iter()
+            .map(|(id, name)| (*id, name.clone()))
+            .collect()
+    })
+}
+
+/// Includes or excludes proposals of the topic, overriding the default.
+#[ic_cdk_macros::update]
+fn set_proposal_topic(topic: String, include: bool) -> Result<(), String> {
+    controller_only()?;
+    let topic = parse_topic(&topic)?;
+    mutate(|s| s.proposal_topics.insert(topic, include));
+    Ok(())
+}
+
+/// Restores the default filter of the topic.
+#[ic_cdk_macros::update]
+fn reset_proposal_topic(topic: String) -> Result<(), String> {
+    controller_only()?;
+    let topic = parse_topic(&topic)?;
+    mutate(|s| s.proposal_topics.remove(&topic))
+        .map(|_| ())
+        .ok_or("topic uses the default filter".into())
+}
+
+/// Lists all topics and whether their proposals are posted.
+#[ic_cdk_macros::query]
+fn list_proposal_topics() -> Vec<(String, bool)> {
+    read(|s| {
+        (0..TOPICS.len() as i32)
+            .map(|topic| (topic_name(topic).to_string(), included(s, topic)))
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        excerpt, included, outcome, parse_topic, topic_name, votes_message, ProposalId,
+        ProposalInfo, Tally,
+    };
+    use crate::State;
+
+    #[test]
+    fn test_excerpt() {
+        assert_eq!(excerpt("  Short summary\n", 400), "Short summary");
+        let summary = "# Title\n\nThis proposal upgrades the canister to the latest version.";
+        assert_eq!(
+            excerpt(summary, 40),
+            "# Title\n\nThis proposal upgrades the…"
+        );
+        assert_eq!(excerpt("äääää", 5), "ää…");
+    }
+
+    #[test]
+    fn test_topics() {
+        let mut state = State::default();
+        let governance = parse_topic("governance").unwrap();
+        let exchange_rate = parse_topic("ExchangeRate").unwrap();
+        assert_eq!(topic_name(governance), "Governance");
+        assert_eq!(topic_name(99), "Unspecified");
+        assert!(parse_topic("Foo").is_err());
+        assert!(included(&state, governance));
+        assert!(!included(&state, exchange_rate));
+        state.proposal_topics.insert(governance, false);
+        state.proposal_topics.insert(exchange_rate, true);
+        assert!(!included(&state, governance));
+        assert!(included(&state, exchange_rate));
+    }
+
+    #[test]
+    fn test_outcome() {
+        let e8s = 100_000_000;
+        let mut info = ProposalInfo {
+            id: Some(ProposalId { id: 130_000 }),
+            status: 1,
+            topic: 4,
+            proposer: None,
+            proposal: None,
+            latest_tally: Some(Tally {
+                yes: 300_000_000 * e8s,
+                no: 100_000_000 * e8s,
+                total: 500_000_000 * e8s,
+            }),
+        };
+        assert_eq!(outcome(&info), None);
+        info.status = 4;
+        assert_eq!(
+            outcome(&info).unwrap(),
+            "Proposal [130000](https://dashboard.internetcomputer.org/proposal/130000) was ✅ adopted and executed.\n\n\
+             Yes: `300’000’000` (75.0%), no: `100’000’000` (25.0%), turnout: `80.0%`"
+        );
+    }
+
+    #[test]
+    fn test_votes_message() {
+        assert_eq!(
+            votes_message(130_000, &[("DFINITY Foundation", 1), ("Synapse", 2)]),
+            "Votes of known neurons on proposal 130000:\n\n\
+             - DFINITY Foundation: ✅ yes\n- Synapse: ❌ no"
+        );
+    }
+}
@@ -81,8 +81,7 @@ fn parse_items(body: Vec<u8>) -> Result<Vec<(u64, String)>, String> {
             let get = |name| {
                 item.descendants()
                     .find(|n| n.tag_name().name() == name)
-                    .map(|n| n.text())
-                    .flatten()
+                    .and_then(|n| n.text())
                     .map(|t| t.trim())
                     .unwrap_or_default()
             };
@@ -97,7 +96,7 @@ fn parse_items(body: Vec<u8>) -> Result<Vec<(u64, String)>, String> {
             let punctuation = if title
                 .chars()
                 .last()
-                .map(|c| vec!['.', '!', '?'].contains(&c))
+                .map(|c| ['.', '!', '?'].contains(&c))
                 .unwrap_or_default()
             {
                 ""
@@ -174,7 +173,7 @@ mod tests {
 </channel>
 </rss>";
 
-        match parse_items(data.as_bytes().into_iter().cloned().collect()) {
+        match parse_items(data.as_bytes().to_vec()) {
             Err(err) => unreachable!("unexpected error: {}", err),
             Ok(items) => {
                 dbg!(&items);
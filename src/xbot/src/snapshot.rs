@@ -0,0 +1,147 @@
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryInto;
+
+use serde::{Deserialize, Serialize};
+
+use super::{labels, Message, State};
+
+// Marks versioned snapshots; the unversioned snapshots of the original layout start with the
+// length of the message queue instead.
+const MAGIC: &[u8; 4] = b"XBOT";
+// Bumped on every change of a deployed state layout, together with a migration of that layout.
+const VERSION: u32 = 1;
+const HEADER_LEN: usize = 16;
+
+/// The original, unversioned layout of the state.
+#[derive(Default, Serialize, Deserialize)]
+struct LegacyState {
+    message_queue: VecDeque<(String, Option<String>)>,
+    logs: VecDeque<String>,
+    last_block: u64,
+    last_best_story: u64,
+    last_rss_story_timestamp: HashMap<String, u64>,
+    modulation: i32,
+    seen_messages: VecDeque<String>,
+}
+
+impl From<LegacyState> for State {
+    fn from(legacy: LegacyState) -> Self {
+        let mut state = State {
+            logs: legacy.logs,
+            last_block: legacy.last_block,
+            last_best_story: legacy.last_best_story,
+            last_rss_story_timestamp: legacy.last_rss_story_timestamp,
+            modulation: legacy.modulation,
+            seen_messages: legacy.seen_messages,
+            ..Default::default()
+        };
+        for (body, realm) in legacy.message_queue {
+            state.message_queue.push_back(Message {
+                id: state.next_message_id,
+                body,
+                realm,
+                reply_to: None,
+                blob_urls: Default::default(),
+                parent_post_id: None,
+            });
+            state.next_message_id += 1;
+        }
+        // The label directory didn't exist yet.
+        labels::seed(&mut state);
+        state
+    }
+}
+
+/// Encodes the state as the magic, the version and the length of the serialized state, followed
+/// by the serialized state.
+pub fn encode(state: &State) -> Result<Vec<u8>, String> {
+    let payload =
+        bincode::serialize(state).map_err(|err| format!("couldn't serialize: {:?}", err))?;
+    let mut bytes = Vec::with_capacity(HEADER_LEN + payload.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(&payload);
+    Ok(bytes)
+}
+
+/// Decodes a snapshot, migrating older layouts. Bytes after the snapshot are ignored, as stable
+/// memory is read in whole pages.
+pub fn decode(bytes: &[u8]) -> Result<State, String> {
+    if bytes.get(0..MAGIC.len()) != Some(&MAGIC[..]) {
+        let legacy: LegacyState = bincode::deserialize(bytes)
+            .map_err(|err| format!("couldn't deserialize the legacy state: {:?}", err))?;
+        return Ok(legacy.into());
+    }
+    let header = bytes
+        .get(0..HEADER_LEN)
+        .ok_or("truncated snapshot header")?;
+    let version = u32::from_le_bytes(header[4..8].try_into().expect("invalid header"));
+    let len = u64::from_le_bytes(header[8..16].try_into().expect("invalid header")) as usize;
+    let payload = bytes
+        .get(HEADER_LEN..HEADER_LEN + len)
+        .ok_or("truncated snapshot")?;
+    match version {
+        VERSION => bincode::deserialize(payload)
+            .map_err(|err| format!("couldn't deserialize the state: {:?}", err)),
+        version => Err(format!("unsupported snapshot version {}", version)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode, LegacyState};
+    use crate::State;
+
+    #[test]
+    fn test_snapshots() {
+        let mut state = State {
+            last_block: 42,
+            next_message_id: 7,
+            ..Default::default()
+        };
+        state.logs.push_back("hello".into());
+        let mut bytes = encode(&state).unwrap();
+        bytes.extend_from_slice(&[0; 100]);
+        let decoded = decode(&bytes).unwrap();
+        assert_eq!(decoded.last_block, 42);
+        assert_eq!(decoded.next_message_id, 7);
+        assert_eq!(decoded.logs, state.logs);
+
+        bytes[4] = 2;
+        assert_eq!(
+            decode(&bytes).err(),
+            Some("unsupported snapshot version 2".to_string())
+        );
+        assert!(decode(&bytes[0..20]).is_err());
+    }
+
+    #[test]
+    fn test_legacy_migration() {
+        let mut legacy = LegacyState {
+            last_block: 23_036_000,
+            modulation: -12,
+            ..Default::default()
+        };
+        legacy
+            .message_queue
+            .push_back(("first".into(), Some("NEWS".into())));
+        legacy.message_queue.push_back(("second".into(), None));
+        legacy.seen_messages.push_back("first".into());
+        let mut bytes = bincode::serialize(&legacy).unwrap();
+        bytes.extend_from_slice(&[0; 100]);
+
+        let state = decode(&bytes).unwrap();
+        assert_eq!(state.last_block, 23_036_000);
+        assert_eq!(state.modulation, -12);
+        assert_eq!(state.seen_messages, vec!["first".to_string()]);
+        let queue = state
+            .message_queue
+            .iter()
+            .map(|msg| (msg.id, msg.body.as_str(), msg.realm.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(queue, vec![(0, "first", Some("NEWS")), (1, "second", None)]);
+        assert_eq!(state.next_message_id, 2);
+        assert!(!state.labels.is_empty());
+    }
+}
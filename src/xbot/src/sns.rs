@@ -0,0 +1,499 @@
+use crate::{controller_only, mutate, read, State};
+
+use super::{proposals, schedule_message};
+use candid::{CandidType, Principal};
+use ic_ledger_types::Tokens;
+use num_format::{Locale, ToFormattedString};
+use serde::{Deserialize, Serialize};
+
+const SNS_WASM_CANISTER_ID: &str = "qaa6y-5yaaa-aaaaq-aaaaq-cai";
+const REALM: &str = "ICP";
+const PROPOSALS_PAGE_SIZE: u32 = 20;
+const MAX_PAGES: usize = 10;
+const MAX_EXCERPT_LEN: usize = 400;
+
+const LIFECYCLE_OPEN: i32 = 2;
+const LIFECYCLE_COMMITTED: i32 = 3;
+const LIFECYCLE_ABORTED: i32 = 4;
+// Marks SNSes whose lifecycle couldn't be read on the first run; it's recorded without an
+// announcement once known.
+const LIFECYCLE_UNKNOWN: i32 = -1;
+
+/// A deployed SNS tracked by the bot.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Sns {
+    pub governance: Principal,
+    pub swap: Principal,
+    pub name: Option<String>,
+    /// The latest lifecycle of the swap seen.
+    pub lifecycle: i32,
+}
+
+/// An SNS whose governance proposals are posted.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct SnsFeed {
+    pub realm: String,
+    pub last_proposal: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+struct Empty {}
+
+#[derive(CandidType, Deserialize)]
+struct DeployedSns {
+    root_canister_id: Option<Principal>,
+    governance_canister_id: Option<Principal>,
+    swap_canister_id: Option<Principal>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct ListDeployedSnsesResponse {
+    instances: Vec<DeployedSns>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct GetLifecycleResponse {
+    lifecycle: Option<i32>,
+}
+
+// Only the fields used by the bot.
+#[derive(CandidType, Deserialize, Default)]
+struct Init {
+    min_participants: Option<u32>,
+    min_direct_participation_icp_e8s: Option<u64>,
+    max_direct_participation_icp_e8s: Option<u64>,
+    sns_token_e8s: Option<u64>,
+    swap_due_timestamp_seconds: Option<u64>,
+    neurons_fund_participation: Option<bool>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct GetInitResponse {
+    init: Option<Init>,
+}
+
+#[derive(CandidType, Deserialize, Default)]
+struct GetDerivedStateResponse {
+    sns_tokens_per_icp: Option<f64>,
+    direct_participant_count: Option<u64>,
+    cf_participant_count: Option<u64>,
+    direct_participation_icp_e8s: Option<u64>,
+    neurons_fund_participation_icp_e8s: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct GetMetadataResponse {
+    name: Option<String>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy)]
+struct ProposalId {
+    id: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+struct ListProposals {
+    include_reward_status: Vec<i32>,
+    before_proposal: Option<ProposalId>,
+    limit: u32,
+    exclude_type: Vec<u64>,
+    include_status: Vec<i32>,
+}
+
+// Only the fields used by the bot; the action of the proposal is omitted.
+#[derive(CandidType, Deserialize)]
+struct Proposal {
+    title: String,
+    summary: String,
+}
+
+#[derive(CandidType, Deserialize)]
+struct ProposalData {
+    id: Option<ProposalId>,
+    proposal: Option<Proposal>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct ListProposalsResponse {
+    proposals: Vec<ProposalData>,
+}
+
+fn icp(e8s: u64) -> String {
+    (e8s / Tokens::SUBDIVIDABLE_BY).to_formatted_string(&Locale::de_CH)
+}
+
+fn dashboard_link(name: &str, root: &str) -> String {
+    format!(
+        "[{}](https://dashboard.internetcomputer.org/sns/{})",
+        name, root
+    )
+}
+
+fn swap_opened_message(name: &str, root: &str, init: &Init, now: u64) -> String {
+    let mut lines = Vec::new();
+    if let Some(tokens) = init.sns_token_e8s {
+        lines.push(format!("- Tokens offered: `{}`", icp(tokens)));
+    }
+    if let (Some(min), Some(max)) = (
+        init.min_direct_participation_icp_e8s,
+        init.max_direct_participation_icp_e8s,
+    ) {
+        lines.push(format!(
+            "- Direct participation: `{}` to `{}` ICP",
+            icp(min),
+            icp(max)
+        ));
+    }
+    if let Some(participants) = init.min_participants {
+        lines.push(format!("- Minimum participants: `{}`", participants));
+    }
+    if let Some(fund) = init.neurons_fund_participation {
+        lines.push(format!(
+            "- Neurons' Fund participation: `{}`",
+            if fund { "yes" } else { "no" }
+        ));
+    }
+    if let Some(due) = init.swap_due_timestamp_seconds {
+        lines.push(format!(
+            "- Closes in `{}` days",
+            due.saturating_sub(now) / (24 * 60 * 60)
+        ));
+    }
+    format!(
+        "🚀 The #SNS swap of {} is open!\n\n{}",
+        dashboard_link(name, root),
+        lines.join("\n")
+    )
+}
+
+fn swap_committed_message(name: &str, root: &str, state: &GetDerivedStateResponse) -> String {
+    let direct = state.direct_participation_icp_e8s.unwrap_or_default();
+    let fund = state.neurons_fund_participation_icp_e8s.unwrap_or_default();
+    let price = state
+        .sns_tokens_per_icp
+        .filter(|tokens| *tokens > 0.0)
+        .map(|tokens| format!(", `{:.4}` ICP per token", 1.0 / tokens))
+        .unwrap_or_default();
+    format!(
+        "✅ The #SNS swap of {} succeeded: `{}` ICP raised from `{}` direct participants and `{}` ICP from `{}` Neurons' Fund participants{}.",
+        dashboard_link(name, root),
+        icp(direct),
+        state.direct_participant_count.unwrap_or_default(),
+        icp(fund),
+        state.cf_participant_count.unwrap_or_default(),
+        price
+    )
+}
+
+async fn call<A: CandidType, R: CandidType + for<'a> Deserialize<'a>>(
+    canister_id: Principal,
+    method: &str,
+    args: A,
+) -> Result<R, String> {
+    let (response,): (R,) = ic_cdk::call(canister_id, method, (args,))
+        .await
+        .map_err(|err| format!("couldn't call {} on {}: {:?}", method, canister_id, err))?;
+    Ok(response)
+}
+
+async fn name(root: &str, sns: &Sns) -> Result<String, String> {
+    if let Some(name) = &sns.name {
+        return Ok(name.clone());
+    }
+    let metadata: GetMetadataResponse = call(sns.governance, "get_metadata", Empty {}).await?;
+    let name = metadata.name.unwrap_or_else(|| root.to_string());
+    mutate(|s| {
+        if let Some(sns) = s.snses.get_mut(root) {
+            sns.name = Some(name.clone());
+        }
+    });
+    Ok(name)
+}
+
+/// Discovers deployed SNSes; on the very first run they're only recorded.
+async fn discover() -> Result<(), String> {
+    let sns_wasm = Principal::from_text(SNS_WASM_CANISTER_ID).expect("invalid SNS-W canister id");
+    let response: ListDeployedSnsesResponse =
+        call(sns_wasm, "list_deployed_snses", Empty {}).await?;
+    let first_run = read(|s| s.snses.is_empty());
+    for instance in response.instances {
+        let (Some(root), Some(governance), Some(swap)) = (
+            instance.root_canister_id,
+            instance.governance_canister_id,
+            instance.swap_canister_id,
+        ) else {
+            continue;
+        };
+        if read(|s| s.snses.contains_key(&root.to_text())) {
+            continue;
+        }
+        let lifecycle = if first_run {
+            let response: Result<GetLifecycleResponse, _> =
+                call(swap, "get_lifecycle", Empty {}).await;
+            match response {
+                Ok(response) => response.lifecycle.unwrap_or_default(),
+                Err(err) => {
+                    log_error(&root.to_text(), err);
+                    LIFECYCLE_UNKNOWN
+                }
+            }
+        } else {
+            Default::default()
+        };
+        mutate(|s| {
+            s.snses.insert(
+                root.to_text(),
+                Sns {
+                    governance,
+                    swap,
+                    name: None,
+                    lifecycle,
+                },
+            )
+        });
+    }
+    Ok(())
+}
+
+fn log_error(root: &str, err: String) {
+    mutate(|s| {
+        s.logs
+            .push_back(format!("Error: SNS {} failed: {}", root, err))
+    });
+}
+
+/// Announces swaps that opened, succeeded or were aborted since the last check.
+async fn check_swaps() {
+    let pending = read(|s| {
+        s.snses
+            .iter()
+            .filter(|(_, sns)| {
+                sns.lifecycle != LIFECYCLE_COMMITTED && sns.lifecycle != LIFECYCLE_ABORTED
+            })
+            .map(|(root, sns)| (root.clone(), sns.clone()))
+            .collect::<Vec<_>>()
+    });
+    for (root, sns) in pending {
+        if let Err(err) = check_swap(&root, &sns).await {
+            log_error(&root, err);
+        }
+    }
+}
+
+async fn check_swap(root: &str, sns: &Sns) -> Result<(), String> {
+    let response: GetLifecycleResponse = call(sns.swap, "get_lifecycle", Empty {}).await?;
+    let lifecycle = response.lifecycle.unwrap_or_default();
+    if lifecycle == sns.lifecycle {
+        return Ok(());
+    }
+    let name = name(root, sns).await?;
+    let msg = match lifecycle {
+        _ if sns.lifecycle == LIFECYCLE_UNKNOWN => None,
+        LIFECYCLE_OPEN => {
+            let response: GetInitResponse = call(sns.swap, "get_init", Empty {}).await?;
+            let now = ic_cdk::api::time() / 1_000_000_000;
+            Some(swap_opened_message(
+                &name,
+                root,
+                &response.init.unwrap_or_default(),
+                now,
+            ))
+        }
+        LIFECYCLE_COMMITTED => {
+            let state: GetDerivedStateResponse =
+                call(sns.swap, "get_derived_state", Empty {}).await?;
+            Some(swap_committed_message(&name, root, &state))
+        }
+        LIFECYCLE_ABORTED => Some(format!(
+            "❌ The #SNS swap of {} was aborted.",
+            dashboard_link(&name, root)
+        )),
+        _ => None,
+    };
+    mutate(|s| {
+        if let Some(msg) = msg {
+            schedule_message(s, msg, Some(REALM.into()));
+        }
+        if let Some(sns) = s.snses.get_mut(root) {
+            sns.lifecycle = lifecycle;
+        }
+    });
+    Ok(())
+}
+
+/// Posts new governance proposals of the configured SNSes to their realms.
+async fn check_proposals() {
+    let feeds = read(|s| {
+        s.sns_feeds
+            .iter()
+            .filter_map(|(root, feed)| {
+                s.snses
+                    .get(root)
+                    .map(|sns| (root.clone(), sns.clone(), feed.clone()))
+            })
+            .collect::<Vec<_>>()
+    });
+    for (root, sns, feed) in feeds {
+        if let Err(err) = check_feed(&root, &sns, &feed).await {
+            log_error(&root, err);
+        }
+    }
+}
+
+async fn check_feed(root: &str, sns: &Sns, feed: &SnsFeed) -> Result<(), String> {
+    let mut new_proposals = Vec::new();
+    let mut before: Option<u64> = None;
+    // Proposals are listed newest first, so we page back until we reach the last seen one.
+    for page in 1..=MAX_PAGES {
+        let args = ListProposals {
+            include_reward_status: Default::default(),
+            before_proposal: before.map(|id| ProposalId { id }),
+            limit: PROPOSALS_PAGE_SIZE,
+            exclude_type: Default::default(),
+            include_status: Default::default(),
+        };
+        let response: ListProposalsResponse = call(sns.governance, "list_proposals", args).await?;
+        let done = response.proposals.len() < PROPOSALS_PAGE_SIZE as usize;
+        for data in response.proposals {
+            let Some(id) = data.id.map(|id| id.id) else {
+                continue;
+            };
+            before = Some(before.unwrap_or(id).min(id));
+            match data.proposal {
+                Some(proposal) if id > feed.last_proposal => new_proposals.push((id, proposal)),
+                _ => {}
+            }
+        }
+        // On the first check of a feed, we only remember the latest proposal.
+        if done || feed.last_proposal == 0 || before.unwrap_or_default() <= feed.last_proposal {
+            break;
+        }
+        if page == MAX_PAGES {
+            log_error(
+                root,
+                format!(
+                    "proposals up to {} were skipped",
+                    before.unwrap_or_default().saturating_sub(1)
+                ),
+            );
+        }
+    }
+    let Some(latest) = new_proposals.iter().map(|(id, _)| *id).max() else {
+        return Ok(());
+    };
+    new_proposals.sort_by_key(|(id, _)| *id);
+    let name = name(root, sns).await?;
+    mutate(|s| {
+        // On the first check of a feed, we only remember the latest proposal.
+        if feed.last_proposal > 0 {
+            for (id, proposal) in new_proposals {
+                let summary = proposals::excerpt(&proposal.summary, MAX_EXCERPT_LEN)
+                    .lines()
+                    .map(|line| format!("> {}", line))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                let msg = format!(
+                    "🗳️ #SNS proposal of {} [{}](https://dashboard.internetcomputer.org/sns/{}/proposal/{}): **{}**\n\n{}",
+                    name, id, root, id, proposal.title, summary
+                );
+                schedule_message(s, msg, Some(feed.realm.clone()));
+            }
+        }
+        if let Some(feed) = s.sns_feeds.get_mut(root) {
+            feed.last_proposal = latest;
+        }
+    });
+    Ok(())
+}
+
+/// Discovers new SNSes and checks the swaps and proposals of the known ones; a failing SNS is
+/// logged and doesn't keep the others from being checked.
+pub async fn go() -> Result<(), String> {
+    let discovery = discover().await;
+    check_swaps().await;
+    check_proposals().await;
+    discovery
+}
+
+/// Posts the governance proposals of the SNS with the given root canister to the realm.
+#[ic_cdk_macros::update]
+fn add_sns_feed(root: String, realm: String) -> Result<(), String> {
+    controller_only()?;
+    let root = Principal::from_text(root.trim())
+        .map_err(|err| format!("invalid root canister id: {:?}", err))?
+        .to_text();
+    if !read(|s: &State| s.snses.contains_key(&root)) {
+        return Err("unknown SNS, it's discovered with the next hourly run".into());
+    }
+    mutate(|s| {
+        s.sns_feeds.insert(
+            root,
+            SnsFeed {
+                realm,
+                last_proposal: 0,
+            },
+        )
+    });
+    Ok(())
+}
+
+#[ic_cdk_macros::update]
+fn remove_sns_feed(root: String) -> Result<(), String> {
+    controller_only()?;
+    mutate(|s| s.sns_feeds.remove(root.trim()))
+        .map(|_| ())
+        .ok_or("no such feed".into())
+}
+
+#[ic_cdk_macros::query]
+fn list_sns_feeds() -> Vec<(String, Option<String>, String)> {
+    read(|s| {
+        s.sns_feeds
+            .iter()
+            .map(|(root, feed)| {
+                let name = s.snses.get(root).and_then(|sns| sns.name.clone());
+                (root.clone(), name, feed.realm.clone())
+            })
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{swap_committed_message, swap_opened_message, GetDerivedStateResponse, Init};
+
+    #[test]
+    fn test_swap_messages() {
+        let e8s = 100_000_000;
+        let init = Init {
+            min_participants: Some(100),
+            min_direct_participation_icp_e8s: Some(100_000 * e8s),
+            max_direct_participation_icp_e8s: Some(500_000 * e8s),
+            sns_token_e8s: Some(25_000_000 * e8s),
+            swap_due_timestamp_seconds: Some(15 * 24 * 60 * 60),
+            neurons_fund_participation: Some(true),
+        };
+        assert_eq!(
+            swap_opened_message("Foo", "root", &init, 24 * 60 * 60),
+            "🚀 The #SNS swap of [Foo](https://dashboard.internetcomputer.org/sns/root) is open!\n\n\
+             - Tokens offered: `25’000’000`\n\
+             - Direct participation: `100’000` to `500’000` ICP\n\
+             - Minimum participants: `100`\n\
+             - Neurons' Fund participation: `yes`\n\
+             - Closes in `14` days"
+        );
+        let state = GetDerivedStateResponse {
+            sns_tokens_per_icp: Some(50.0),
+            direct_participant_count: Some(1_234),
+            cf_participant_count: Some(56),
+            direct_participation_icp_e8s: Some(400_000 * e8s),
+            neurons_fund_participation_icp_e8s: Some(100_000 * e8s),
+        };
+        assert_eq!(
+            swap_committed_message("Foo", "root", &state),
+            "✅ The #SNS swap of [Foo](https://dashboard.internetcomputer.org/sns/root) succeeded: \
+             `400’000` ICP raised from `1234` direct participants and `100’000` ICP from `56` \
+             Neurons' Fund participants, `0.0200` ICP per token."
+        );
+    }
+}
@@ -0,0 +1,156 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    convert::TryFrom,
+};
+
+use crate::{mutate, read, State};
+
+use super::{
+    ledger::{Block, Operation},
+    schedule_message,
+};
+use candid::{CandidType, Nat};
+use ic_ledger_types::{AccountIdentifier, Tokens, MAINNET_LEDGER_CANISTER_ID};
+use num_format::{Locale, ToFormattedString};
+use serde::{Deserialize, Serialize};
+
+const REALM: &str = "ICP";
+// Daily samples kept in the state.
+const MAX_SAMPLES: usize = 400;
+const DAY_SECS: u64 = 24 * 60 * 60;
+
+/// A daily sample of the ICP supply.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Sample {
+    pub timestamp: u64,
+    pub total_supply_e8s: u64,
+    /// ICP burned by the CMC for cycles since the previous sample.
+    pub burned_e8s: u64,
+}
+
+/// Accounts burns of the block from the given CMC deposit accounts to the current day.
+pub fn record(state: &mut State, block: &Block, cmc_deposits: &HashSet<AccountIdentifier>) {
+    if let Some(Operation::Burn { from, amount, .. }) = block.transaction.operation.as_ref() {
+        if cmc_deposits.contains(from) {
+            state.icp_burned_e8s += amount.e8s();
+        }
+    }
+}
+
+fn signed_icp(e8s: i64) -> String {
+    let sign = if e8s > 0 { "+" } else { "" };
+    format!(
+        "{}{}",
+        sign,
+        (e8s / Tokens::SUBDIVIDABLE_BY as i64).to_formatted_string(&Locale::de_CH)
+    )
+}
+
+fn icp(e8s: u64) -> String {
+    (e8s / Tokens::SUBDIVIDABLE_BY).to_formatted_string(&Locale::de_CH)
+}
+
+/// Describes the supply change and burns since the sample the given period ago.
+fn period(history: &VecDeque<Sample>, current: &Sample, name: &str, secs: u64) -> Option<String> {
+    let start = history
+        .iter()
+        .rev()
+        .find(|sample| sample.timestamp + secs <= current.timestamp)?;
+    let burned = history
+        .iter()
+        .filter(|sample| sample.timestamp > start.timestamp)
+        .map(|sample| sample.burned_e8s)
+        .sum::<u64>()
+        + current.burned_e8s;
+    let change = current.total_supply_e8s as i64 - start.total_supply_e8s as i64;
+    let change = match change {
+        0 => "no net change".to_string(),
+        _ => format!(
+            "net {} of `{}` ICP (`{:+.3}%`)",
+            if change > 0 { "inflation" } else { "deflation" },
+            signed_icp(change),
+            change as f64 * 100.0 / start.total_supply_e8s.max(1) as f64
+        ),
+    };
+    Some(format!(
+        "- {}: {}, `{}` ICP burned for cycles",
+        name,
+        change,
+        icp(burned)
+    ))
+}
+
+fn report(history: &VecDeque<Sample>, current: &Sample) -> String {
+    let mut lines = vec![format!(
+        "- Total supply: `{}` ICP",
+        icp(current.total_supply_e8s)
+    )];
+    // Allow for the timer drifting by a few minutes.
+    let tolerance = 60 * 60;
+    lines.extend(period(history, current, "24h", DAY_SECS - tolerance));
+    lines.extend(period(
+        history,
+        current,
+        "30 days",
+        30 * DAY_SECS - tolerance,
+    ));
+    format!("🔥 #ICP supply report\n\n{}", lines.join("\n"))
+}
+
+/// Records the current supply with the burns seen since the last run and posts the report.
+pub async fn go() -> Result<(), String> {
+    let (supply,): (Nat,) = ic_cdk::call(MAINNET_LEDGER_CANISTER_ID, "icrc1_total_supply", ())
+        .await
+        .map_err(|err| format!("couldn't get the total supply: {:?}", err))?;
+    let total_supply_e8s = u64::try_from(&supply.0)
+        .map_err(|err| format!("couldn't convert {}: {:?}", supply, err))?;
+    mutate(|s| {
+        let sample = Sample {
+            timestamp: ic_cdk::api::time() / 1_000_000_000,
+            total_supply_e8s,
+            burned_e8s: std::mem::take(&mut s.icp_burned_e8s),
+        };
+        let msg = report(&s.supply_history, &sample);
+        schedule_message(s, msg, Some(REALM.into()));
+        s.supply_history.push_back(sample);
+        while s.supply_history.len() > MAX_SAMPLES {
+            s.supply_history.pop_front();
+        }
+    });
+    Ok(())
+}
+
+#[ic_cdk_macros::query]
+fn supply_history() -> Vec<Sample> {
+    read(|s| s.supply_history.iter().cloned().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{report, Sample, DAY_SECS};
+    use std::collections::VecDeque;
+
+    #[test]
+    fn test_report() {
+        let e8s = 100_000_000;
+        let history = (0..30)
+            .map(|day| Sample {
+                timestamp: day * DAY_SECS,
+                total_supply_e8s: (500_000_000 + day * 10_000) * e8s,
+                burned_e8s: 1_000 * e8s,
+            })
+            .collect::<VecDeque<_>>();
+        let current = Sample {
+            timestamp: 30 * DAY_SECS,
+            total_supply_e8s: (500_000_000 + 280_000) * e8s,
+            burned_e8s: 2_000 * e8s,
+        };
+        assert_eq!(
+            report(&history, &current),
+            "🔥 #ICP supply report\n\n\
+             - Total supply: `500’280’000` ICP\n\
+             - 24h: net deflation of `-10’000` ICP (`-0.002%`), `2’000` ICP burned for cycles\n\
+             - 30 days: net inflation of `+280’000` ICP (`+0.056%`), `31’000` ICP burned for cycles"
+        );
+    }
+}
@@ -0,0 +1,329 @@
+use html5ever::{parse_document, tendril::TendrilSink};
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpMethod, HttpResponse, TransformArgs,
+    TransformContext,
+};
+use markup5ever_rcdom::{Handle, NodeData, RcDom};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{mutate, read, schedule_message_with_blobs};
+
+const CYCLES: u128 = 30_000_000_000;
+// Photos exceeding this size are not attached.
+const MAX_PHOTO_BYTES: u64 = 400_000;
+
+/// A message extracted from a channel preview page.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct TelegramMessage {
+    id: u64,
+    text: String,
+    photo: Option<String>,
+}
+
+/// A public Telegram channel read from its `t.me/s` preview page.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Channel {
+    pub name: String,
+    /// Only messages matching this regex are posted.
+    pub filter: String,
+    /// Regex replacements applied to every posted message.
+    pub replacements: Vec<(String, String)>,
+    pub hashtag: String,
+    pub realm: String,
+}
+
+pub fn channels() -> Vec<Channel> {
+    vec![Channel {
+        name: "WatcherGuru".into(),
+        filter: "JUST IN".into(),
+        replacements: vec![
+            // Drops the "Via @WatcherGuru" attribution, linked or not.
+            (
+                r"(?i)(\bvia\s+)?\[?@WatcherGuru\]?(\([^)]*\))?".into(),
+                "".into(),
+            ),
+            (r"(\*\*)?JUST IN:?(\*\*)?:?".into(), "**JUST IN**:".into()),
+        ],
+        hashtag: "WatcherGuru".into(),
+        realm: "NEWS".into(),
+    }]
+}
+
+#[ic_cdk_macros::query]
+fn transform_tg_response(mut args: TransformArgs) -> HttpResponse {
+    args.response.headers.clear();
+    args.response.body = serde_json::from_slice(&args.context)
+        .map_err(|err| format!("couldn't parse the channel: {:?}", err))
+        .and_then(|channel| {
+            extract_messages(&channel, &String::from_utf8_lossy(&args.response.body))
+        })
+        .and_then(|messages| {
+            serde_json::to_vec(&messages).map_err(|err| format!("serialization failed: {:?}", err))
+        })
+        .unwrap_or_default();
+    args.response
+}
+
+#[ic_cdk_macros::query]
+fn transform_tg_photo(mut args: TransformArgs) -> HttpResponse {
+    args.response.headers.clear();
+    args.response
+}
+
+/// Returns the messages of the page passing the channel filter with their text converted to
+/// Markdown, ordered by id.
+fn extract_messages(channel: &Channel, page: &str) -> Result<Vec<TelegramMessage>, String> {
+    let regex = |pattern: &str| {
+        Regex::new(pattern).map_err(|err| format!("invalid regex {}: {:?}", pattern, err))
+    };
+    let filter = regex(&channel.filter)?;
+    let replacements = channel
+        .replacements
+        .iter()
+        .map(|(pattern, replacement)| regex(pattern).map(|re| (re, replacement)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let dom = parse_document(RcDom::default(), Default::default()).one(page);
+    let mut bubbles = Vec::new();
+    find_bubbles(&dom.document, &mut bubbles);
+
+    let mut messages = Vec::new();
+    for (id, bubble) in bubbles {
+        let Some(text_node) = find_by_class(&bubble, "tgme_widget_message_text") else {
+            continue;
+        };
+        let mut text = String::new();
+        to_markdown(&text_node, &mut text);
+        let mut text = text.trim().to_string();
+        if !filter.is_match(&text) {
+            continue;
+        }
+        for (re, replacement) in &replacements {
+            text = re.replace_all(&text, replacement.as_str()).to_string();
+        }
+        let photo = find_by_class(&bubble, "tgme_widget_message_photo_wrap")
+            .and_then(|node| attr(&node, "style"))
+            .and_then(|style| photo_url(&style));
+        messages.push(TelegramMessage {
+            id,
+            text: text.trim().to_string(),
+            photo,
+        });
+    }
+    messages.sort_by_key(|message| message.id);
+    Ok(messages)
+}
+
+/// Extracts the url from a `background-image:url('...')` style declaration.
+fn photo_url(style: &str) -> Option<String> {
+    let (_, rest) = style.split_once("background-image:url(")?;
+    let (url, _) = rest.split_once(')')?;
+    let url = url.trim_matches(|c| c == '\'' || c == '"');
+    Some(if url.starts_with("//") {
+        format!("https:{}", url)
+    } else {
+        url.to_string()
+    })
+}
+
+pub async fn fetch_photo(url: &str) -> Result<Vec<u8>, String> {
+    let request = CanisterHttpRequestArgument {
+        url: url.to_string(),
+        max_response_bytes: Some(MAX_PHOTO_BYTES),
+        method: HttpMethod::GET,
+        transform: Some(TransformContext::from_name(
+            "transform_tg_photo".to_string(),
+            Default::default(),
+        )),
+        ..Default::default()
+    };
+    let (response,) = http_request(request, CYCLES)
+        .await
+        .map_err(|err| format!("http_request failed: {:?}", err))?;
+    Ok(response.body)
+}
+
+fn attr(node: &Handle, name: &str) -> Option<String> {
+    match &node.data {
+        NodeData::Element { attrs, .. } => attrs
+            .borrow()
+            .iter()
+            .find(|attr| attr.name.local.as_ref() == name)
+            .map(|attr| attr.value.to_string()),
+        _ => None,
+    }
+}
+
+fn has_class(node: &Handle, class: &str) -> bool {
+    attr(node, "class")
+        .map(|classes| classes.split_whitespace().any(|c| c == class))
+        .unwrap_or_default()
+}
+
+/// Collects all message bubbles, i.e. elements carrying a `data-post="Channel/12345"` attribute.
+fn find_bubbles(node: &Handle, bubbles: &mut Vec<(u64, Handle)>) {
+    if let Some(id) = attr(node, "data-post")
+        .and_then(|post| post.rsplit('/').next().and_then(|id| id.parse().ok()))
+    {
+        bubbles.push((id, node.clone()));
+        return;
+    }
+    for child in node.children.borrow().iter() {
+        find_bubbles(child, bubbles);
+    }
+}
+
+/// Finds the first descendant with the given class, skipping quoted replies.
+fn find_by_class(node: &Handle, class: &str) -> Option<Handle> {
+    for child in node.children.borrow().iter() {
+        if has_class(child, "tgme_widget_message_reply") {
+            continue;
+        }
+        if has_class(child, class) {
+            return Some(child.clone());
+        }
+        if let Some(node) = find_by_class(child, class) {
+            return Some(node);
+        }
+    }
+    None
+}
+
+/// Renders the text of a message as Markdown, keeping links, bold, italic and line breaks.
+fn to_markdown(node: &Handle, out: &mut String) {
+    let children = |out: &mut String| {
+        for child in node.children.borrow().iter() {
+            to_markdown(child, out);
+        }
+    };
+    let wrap = |out: &mut String, marker: &str| {
+        let mut inner = String::new();
+        for child in node.children.borrow().iter() {
+            to_markdown(child, &mut inner);
+        }
+        if inner.trim().is_empty() {
+            out.push_str(&inner);
+        } else {
+            out.push_str(&format!("{}{}{}", marker, inner.trim(), marker));
+        }
+    };
+    match &node.data {
+        NodeData::Text { contents } => out.push_str(&contents.borrow().replace('\n', " ")),
+        NodeData::Element { name, .. } => match name.local.as_ref() {
+            "br" => out.push_str("  \n"),
+            // Telegram wraps emojis into `<i class="emoji"><b>..</b></i>`.
+            "i" if has_class(node, "emoji") => out.push_str(&text(node)),
+            "b" | "strong" => wrap(out, "**"),
+            "i" | "em" => wrap(out, "_"),
+            "s" | "del" => wrap(out, "~~"),
+            "code" => wrap(out, "`"),
+            "a" => {
+                let mut label = String::new();
+                for child in node.children.borrow().iter() {
+                    to_markdown(child, &mut label);
+                }
+                match attr(node, "href") {
+                    // Hashtags and mentions link to relative search pages.
+                    Some(href) if href.starts_with("http") => {
+                        out.push_str(&format!("[{}]({})", label.trim(), href))
+                    }
+                    _ => out.push_str(&label),
+                }
+            }
+            _ => children(out),
+        },
+        _ => children(out),
+    }
+}
+
+fn text(node: &Handle) -> String {
+    match &node.data {
+        NodeData::Text { contents } => contents.borrow().to_string(),
+        _ => node.children.borrow().iter().map(text).collect(),
+    }
+}
+
+pub async fn go(channel: &Channel) -> Result<(), String> {
+    let context =
+        serde_json::to_vec(channel).map_err(|err| format!("serialization failed: {:?}", err))?;
+    let request = CanisterHttpRequestArgument {
+        url: format!("https://t.me/s/{}", channel.name),
+        max_response_bytes: Some(100_000),
+        method: HttpMethod::GET,
+        transform: Some(TransformContext::from_name(
+            "transform_tg_response".to_string(),
+            context,
+        )),
+        ..Default::default()
+    };
+
+    let (response,) = http_request(request, CYCLES)
+        .await
+        .map_err(|err| format!("http_request failed: {:?}", err))?;
+    let messages: Vec<TelegramMessage> = serde_json::from_slice(&response.body)
+        .map_err(|err| format!("json parsing failed: {:?}", err))?;
+    let Some(cursor) = read(|s| s.telegram_cursors.get(&channel.name).copied()) else {
+        // On the first run of a channel, we only remember the latest message.
+        if let Some(latest) = messages.iter().map(|m| m.id).max() {
+            mutate(|s| s.telegram_cursors.insert(channel.name.clone(), latest));
+        }
+        return Ok(());
+    };
+
+    for TelegramMessage { id, text, photo } in messages.into_iter().filter(|m| m.id > cursor) {
+        let mut body = text;
+        let mut blob_urls = Vec::new();
+        // Photos are only fetched right before posting, after the deduplication.
+        if let Some(url) = photo {
+            let blob_id = format!("tg{}", id);
+            body = format!("{}\n\n![photo](/blob/{})", body, blob_id);
+            blob_urls.push((blob_id, url));
+        }
+        mutate(|state| {
+            schedule_message_with_blobs(
+                state,
+                format!("{}  \n#{}", body, channel.hashtag),
+                Some(channel.realm.clone()),
+                blob_urls,
+            );
+            state.telegram_cursors.insert(channel.name.clone(), id);
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{channels, extract_messages, TelegramMessage};
+
+    #[test]
+    fn test_parsing() {
+        let page = include_str!("../fixtures/watcherguru.html");
+        let channel = channels().remove(0);
+        let messages = extract_messages(&channel, page).unwrap();
+        assert_eq!(
+            messages,
+            vec![
+                TelegramMessage {
+                    id: 71203,
+                    text: "🇺🇸 **JUST IN**: US Treasury Secretary says the government will keep buying \
+                     $BTC.  \n  \nFull Story: [watcher.guru/news/us-treasury-bitcoin]\
+                     (https://watcher.guru/news/us-treasury-bitcoin)"
+                        .to_string(),
+                    photo: Some(
+                        "https://cdn4.cdn-telegram.org/file/WJ3qvSx2fPj8XdR.jpg".to_string()
+                    ),
+                },
+                TelegramMessage {
+                    id: 71205,
+                    text: "**JUST IN**: Bitcoin ETF inflows <> outflows ratio hits record \"3:1\" & \
+                     S&P 500 doesn't care"
+                        .to_string(),
+                    photo: None,
+                },
+            ]
+        );
+    }
+}
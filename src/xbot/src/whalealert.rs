@@ -1,143 +1,577 @@
-use crate::{mutate, read};
+use crate::{mutate, read, State};
 
-use super::schedule_message;
-use ic_ledger_types::{
-    Operation, Tokens, MAINNET_LEDGER_CANISTER_ID, {GetBlocksArgs, QueryBlocksResponse},
+use super::{
+    anomaly, exchangerate, labels,
+    ledger::{self, Block, Operation, Transaction},
+    memo::{self, Memo},
+    netflow, schedule_message, schedule_reply, supply,
 };
+use candid::Principal;
+use ic_ledger_types::{AccountIdentifier, Subaccount, Tokens, MAINNET_GOVERNANCE_CANISTER_ID};
 use num_format::{Locale, ToFormattedString};
-use std::collections::HashMap;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
-const WHALE_ALERT: Tokens = Tokens::from_e8s(8000000000000); // 80k ICP
+/// Minimal USD value of an operation triggering an alert, and the minimal amount of ICP used if
+/// no recent ICP/USD rate is known.
+struct Threshold {
+    usd: u64,
+    icp: u64,
+}
+
+const TRANSFER_ALERT: Threshold = Threshold {
+    usd: 500_000,
+    icp: 80_000,
+};
+const MINT_ALERT: Threshold = Threshold {
+    usd: 500_000,
+    icp: 80_000,
+};
+const BURN_ALERT: Threshold = Threshold {
+    usd: 100_000,
+    icp: 20_000,
+};
+const APPROVE_ALERT: Threshold = Threshold {
+    usd: 1_000_000,
+    icp: 150_000,
+};
+const NEURON_STAKE_ALERT: Threshold = Threshold {
+    usd: 250_000,
+    icp: 40_000,
+};
+const CYCLES_ALERT: Threshold = Threshold {
+    usd: 50_000,
+    icp: 8_000,
+};
 const BATCH_SIZE: u64 = 1000;
+const WATCHLIST_REALM: &str = "ICP";
+// Maximal length of a post; further lines are continued in replies.
+const MAX_POST_LEN: usize = 3000;
+
+/// An alert line, or transfers between the same pair of accounts merged into one line.
+enum Alert {
+    Line(String),
+    Transfers {
+        from: AccountIdentifier,
+        to: AccountIdentifier,
+        count: u64,
+        total: Tokens,
+        /// The memo shared by all transfers, if any.
+        memo: Option<Memo>,
+    },
+}
+
+/// Alerts of one run in the order of their first occurrence.
+#[derive(Default)]
+struct Alerts {
+    alerts: Vec<Alert>,
+    transfers: HashMap<(AccountIdentifier, AccountIdentifier), usize>,
+}
+
+impl Alerts {
+    fn add_transfer(
+        &mut self,
+        from: AccountIdentifier,
+        to: AccountIdentifier,
+        amount: Tokens,
+        new_memo: Option<Memo>,
+    ) {
+        match self.transfers.get(&(from, to)) {
+            Some(index) => {
+                if let Alert::Transfers {
+                    count, total, memo, ..
+                } = &mut self.alerts[*index]
+                {
+                    *count += 1;
+                    *total += amount;
+                    if *memo != new_memo {
+                        *memo = None;
+                    }
+                }
+            }
+            None => {
+                self.transfers.insert((from, to), self.alerts.len());
+                self.alerts.push(Alert::Transfers {
+                    from,
+                    to,
+                    count: 1,
+                    total: amount,
+                    memo: new_memo,
+                });
+            }
+        }
+    }
+
+    fn render(&self, state: &State) -> Vec<String> {
+        self.alerts
+            .iter()
+            .map(|alert| match alert {
+                Alert::Line(line) => line.clone(),
+                Alert::Transfers {
+                    from,
+                    to,
+                    count,
+                    total,
+                    memo,
+                } => format!(
+                    "- {} transferred from {} to {}{}{}.",
+                    amount_with_usd(state, *total),
+                    account(state, from),
+                    account(state, to),
+                    if *count > 1 {
+                        format!(" in {} transfers", count)
+                    } else {
+                        String::default()
+                    },
+                    memo.as_ref()
+                        .map(|memo| memo::render(state, memo, true))
+                        .unwrap_or_default()
+                ),
+            })
+            .collect()
+    }
+}
+
+/// The purpose of a transfer, derived from its destination and memo.
+enum Purpose {
+    NeuronStake,
+    CanisterTopUp,
+    CanisterCreation,
+    CyclesMint,
+}
 
 pub async fn go() -> Result<(), String> {
-    let mut total_blocks = 0;
     let mut max_amount = 0;
-    let resolver = |acc: &str| {
-        get_accounts()
-            .get(acc)
-            .unwrap_or(&acc[0..6].to_string().as_str())
-            .to_string()
-    };
     let start = read(|s| s.last_block);
-    let mut last_block = start;
+    let mut next_block = start;
+    let mut alerts = Alerts::default();
+    let mut watchlist_msgs: BTreeMap<String, Vec<String>> = Default::default();
+    // On errors, the alerts of the batches already processed are still scheduled, as the cursor
+    // already moved past them.
+    let mut result = Ok(());
 
-    for step in 0..1000 {
-        let start = read(|s| s.last_block);
-        let args = GetBlocksArgs {
-            start,
-            length: BATCH_SIZE,
+    for _ in 0..1000 {
+        let response = match ledger::query_blocks(next_block, BATCH_SIZE).await {
+            Ok(response) => response,
+            Err(err) => {
+                result = Err(err);
+                break;
+            }
         };
-        let (response,): (QueryBlocksResponse,) =
-            ic_cdk::call(MAINNET_LEDGER_CANISTER_ID, "query_blocks", (args,))
-                .await
-                .map_err(|err| format!("canister call failed: {:?}", err))?;
-        if step == 0 && response.blocks.is_empty() {
-            mutate(|s| s.last_block = response.first_block_index);
-            continue;
+        // On the very first run, we start from the tip of the chain.
+        if next_block == 0 {
+            next_block = response.chain_length;
+            break;
         }
-        if (response.blocks.len() as u64) < BATCH_SIZE {
+
+        let chain_length = response.chain_length;
+        let blocks = match ledger::fetch_blocks(next_block, response).await {
+            Ok(blocks) => blocks,
+            Err(err) => {
+                result = Err(err);
+                break;
+            }
+        };
+        if blocks.is_empty() {
             break;
         }
-        total_blocks += response.blocks.len();
-        last_block = start + total_blocks as u64;
-        mutate(|s| s.last_block = last_block);
-        let mut msgs = Vec::new();
-        for block in &response.blocks {
-            if let Some(Operation::Transfer {
-                from, to, amount, ..
-            }) = block.transaction.operation
-            {
-                max_amount = max_amount.max(amount.e8s());
-                if amount > WHALE_ALERT {
-                    msgs.push( format!(
-                    "- `{}` ICP transferred from [{}](https://dashboard.internetcomputer.org/account/{}) to [{}](https://dashboard.internetcomputer.org/account/{}).",
-                    icp(amount), resolver(&from.to_string()), from, resolver(&to.to_string()), to
-                ))
+        // Once notified, the CMC burns the ICP sent to its subaccounts, usually within seconds.
+        let cmc_burns = blocks
+            .iter()
+            .filter_map(|block| match block.transaction.operation.as_ref()? {
+                Operation::Burn { from, .. } => Some(*from),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+        mutate(|s| {
+            // Only the burns of accounts receiving CMC deposits were converted to cycles.
+            let cmc_deposits = blocks
+                .iter()
+                .filter(|block| {
+                    matches!(
+                        purpose(s, &block.transaction, &cmc_burns),
+                        Some(
+                            Purpose::CanisterTopUp
+                                | Purpose::CanisterCreation
+                                | Purpose::CyclesMint
+                        )
+                    )
+                })
+                .filter_map(|block| match block.transaction.operation.as_ref()? {
+                    Operation::Transfer { to, .. } => Some(*to),
+                    _ => None,
+                })
+                .collect::<HashSet<_>>();
+            for block in &blocks {
+                if let Some(amount) = amount(block) {
+                    max_amount = max_amount.max(amount.e8s());
                 }
-            };
-        }
-        if !msgs.is_empty() {
-            let full_msg = format!("🚨 #WhaleAlert\n\n{}", msgs.join("\n"));
-            mutate(|s| schedule_message(s, full_msg.clone(), Some("ICP".into())));
+                netflow::record(s, block);
+                anomaly::record(s, block);
+                supply::record(s, block, &cmc_deposits);
+                alert(s, block, &cmc_burns, &mut alerts);
+                for (realm, msg) in watch(s, block, &cmc_burns) {
+                    watchlist_msgs.entry(realm).or_default().push(msg);
+                }
+            }
+        });
+        next_block += blocks.len() as u64;
+        // The cursor moves together with the recorded statistics.
+        mutate(|s| s.last_block = next_block);
+        if next_block >= chain_length {
+            break;
         }
     }
 
     mutate(|s| {
+        s.last_block = next_block;
+        let msgs = alerts.render(s);
+        schedule_thread(s, "🚨 #WhaleAlert", &msgs, "ICP");
+        for (realm, msgs) in watchlist_msgs {
+            schedule_thread(s, "👀 #WatchList", &msgs, &realm);
+        }
+        if let Some(msg) = anomaly::check(s) {
+            schedule_message(s, msg, Some("ICP".into()));
+        }
         s.logs.push_back(format!(
-        "Total transactions pulled: {} (max e8s: {}, start: {}, last_block: {}, next_block: {})",
-        total_blocks,
-        icp(Tokens::from_e8s(max_amount)),
-        start,
-        start + total_blocks as u64,
-        last_block))
+            "Total transactions pulled: {} (max e8s: {}, start: {}, next_block: {})",
+            next_block - start,
+            icp(Tokens::from_e8s(max_amount)),
+            start,
+            next_block
+        ))
     });
-    Ok(())
-}
-
-pub fn get_accounts<'a>() -> HashMap<&'a str, &'a str> {
-    let mut map: HashMap<_, _> = Default::default();
-
-    map.insert(
-        "d3e13d4777e22367532053190b6c6ccf57444a61337e996242b1abfb52cf92c8",
-        "Binance",
-    );
-    map.insert(
-        "220c3a33f90601896e26f76fa619fe288742df1fa75426edfaf759d39f2455a5",
-        "Binance",
-    );
-    map.insert(
-        "935b1a3adc28fd68cacc95afcdec62e985244ce0cfbbb12cdc7d0b8d198b416d",
-        "Huobi",
-    );
-    map.insert(
-        "e7a879ea563d273c46dd28c1584eaa132fad6f3e316615b3eb657d067f3519b5",
-        "Okex",
-    );
-    map.insert(
-        "4dfa940def17f1427ae47378c440f10185867677109a02bc8374fc25b9dee8af",
-        "Coinbase",
-    );
-    map.insert(
-        "a6ed987d89796f921c8a49d275ec7c9aa04e75a8fc8cd2dbaa5da799f0215ab0",
-        "Coinbase",
-    );
-    map.insert(
-        "449ce7ad1298e2ed2781ed379aba25efc2748d14c60ede190ad7621724b9e8b2",
-        "Coinbase",
-    );
-    map.insert(
-        "660b1680dafeedaa68c1f1f4cf8af42ed1dfb8564646efe935a2b9a48528b605",
-        "Coinbase",
-    );
-    map.insert(
-        "dd15f3040edab88d2e277f9d2fa5cc11616ebf1442279092e37924ab7cce8a74",
-        "Coinbase",
-    );
-    map.insert(
-        "4878d23a09b554157b31323004e1cc053567671426ca4eec7b7e835db607b965",
-        "Coinbase",
-    );
-    map.insert(
-        "8fe706db7b08f957a15199e07761039a7718937aabcc0fe48bc380a4daf9afb0",
-        "Gate",
-    );
-    map.insert(
-        "efa01544f509c56dd85449edf2381244a48fad1ede5183836229c00ab00d52df",
-        "KuCoin",
-    );
-    map.insert(
-        "040834c30cdf5d7a13aae8b57d94ae2d07eefe2bc3edd8cf88298730857ac2eb",
-        "Kraken",
-    );
-    map.insert(
-        "609d3e1e45103a82adc97d4f88c51f78dedb25701e8e51e8c4fec53448aadc29",
-        "Binance Cold Storage",
-    );
-
-    map
+    result
+}
+
+pub fn amount(block: &Block) -> Option<Tokens> {
+    transaction_amount(&block.transaction)
+}
+
+fn transaction_amount(transaction: &Transaction) -> Option<Tokens> {
+    match transaction.operation.as_ref()? {
+        Operation::Mint { amount, .. }
+        | Operation::Burn { amount, .. }
+        | Operation::Transfer { amount, .. } => Some(*amount),
+        Operation::Approve { allowance, .. } => Some(*allowance),
+    }
+}
+
+/// Returns the governance account of the neuron staked by the controller with the given nonce.
+fn neuron_account(controller: &Principal, nonce: u64) -> AccountIdentifier {
+    let mut hasher = Sha256::new();
+    hasher.update([0x0c]);
+    hasher.update(b"neuron-stake");
+    hasher.update(controller.as_slice());
+    hasher.update(nonce.to_be_bytes());
+    AccountIdentifier::new(
+        &MAINNET_GOVERNANCE_CANISTER_ID,
+        &Subaccount(hasher.finalize().into()),
+    )
+}
+
+/// Classifies transfers to neuron accounts and to the cycles minting canister. Neuron accounts
+/// are recognized if registered, or if derivable from a known principal sending the stake, whose
+/// memo is the neuron nonce. Since CMC subaccounts can't be derived without the target canister,
+/// a CMC memo only counts if the recipient is among the given accounts burned by the CMC.
+fn purpose(
+    state: &State,
+    transaction: &Transaction,
+    cmc_burns: &HashSet<AccountIdentifier>,
+) -> Option<Purpose> {
+    let Some(Operation::Transfer { from, to, .. }) = transaction.operation.as_ref() else {
+        return None;
+    };
+    let memo = memo::numeric(transaction);
+    match memo {
+        memo::MEMO_TOP_UP_CANISTER if cmc_burns.contains(to) => {
+            return Some(Purpose::CanisterTopUp)
+        }
+        memo::MEMO_CREATE_CANISTER if cmc_burns.contains(to) => {
+            return Some(Purpose::CanisterCreation)
+        }
+        memo::MEMO_MINT_CYCLES if cmc_burns.contains(to) => return Some(Purpose::CyclesMint),
+        _ => {}
+    }
+    let to = to.to_string();
+    if state.neuron_accounts.contains(&to)
+        || labels::owner(state, &from.to_string())
+            .map(|owner| neuron_account(&owner, memo).to_string() == to)
+            .unwrap_or_default()
+    {
+        return Some(Purpose::NeuronStake);
+    }
+    None
+}
+
+fn threshold(
+    state: &State,
+    transaction: &Transaction,
+    cmc_burns: &HashSet<AccountIdentifier>,
+) -> Option<&'static Threshold> {
+    Some(match transaction.operation.as_ref()? {
+        Operation::Transfer { .. } => match purpose(state, transaction, cmc_burns) {
+            Some(Purpose::NeuronStake) => &NEURON_STAKE_ALERT,
+            Some(_) => &CYCLES_ALERT,
+            None => &TRANSFER_ALERT,
+        },
+        Operation::Mint { .. } => &MINT_ALERT,
+        Operation::Burn { .. } => &BURN_ALERT,
+        Operation::Approve { .. } => &APPROVE_ALERT,
+    })
+}
+
+/// Returns all accounts involved in the operation.
+pub fn accounts(operation: &Operation) -> Vec<&AccountIdentifier> {
+    match operation {
+        Operation::Mint { to, .. } => vec![to],
+        Operation::Burn { from, spender, .. } => std::iter::once(from).chain(spender).collect(),
+        Operation::Transfer {
+            from, to, spender, ..
+        } => vec![from, to].into_iter().chain(spender).collect(),
+        Operation::Approve { from, spender, .. } => vec![from, spender],
+    }
+}
+
+fn account(state: &State, account: &AccountIdentifier) -> String {
+    format!(
+        "[{}](https://dashboard.internetcomputer.org/account/{})",
+        labels::resolve(state, &account.to_string()),
+        account
+    )
+}
+
+fn amount_with_usd(state: &State, amount: Tokens) -> String {
+    let tokens = amount.e8s() as f64 / Tokens::SUBDIVIDABLE_BY as f64;
+    format!(
+        "`{}` ICP{}",
+        icp(amount),
+        exchangerate::usd_value(state, "ICP", tokens)
+            .map(exchangerate::usd)
+            .unwrap_or_default()
+    )
+}
+
+/// Describes the operation of the transaction with wording specific to its type and purpose.
+fn describe(
+    state: &State,
+    transaction: &Transaction,
+    cmc_burns: &HashSet<AccountIdentifier>,
+) -> Option<String> {
+    let operation = transaction.operation.as_ref()?;
+    if let (
+        Operation::Transfer {
+            from, to, amount, ..
+        },
+        Some(purpose),
+    ) = (operation, purpose(state, transaction, cmc_burns))
+    {
+        let amount = amount_with_usd(state, *amount);
+        let from = account(state, from);
+        return Some(match purpose {
+            Purpose::NeuronStake => format!(
+                "{} staked into a [neuron](https://dashboard.internetcomputer.org/account/{}) by {}",
+                amount, to, from
+            ),
+            Purpose::CanisterTopUp => {
+                format!("{} burned for cycles by {} to top up a canister", amount, from)
+            }
+            Purpose::CanisterCreation => {
+                format!("{} burned for cycles by {} to create a canister", amount, from)
+            }
+            Purpose::CyclesMint => {
+                format!("{} burned for cycles by {} for the cycles ledger", amount, from)
+            }
+        });
+    }
+    let memo = memo::decode(transaction)
+        .map(|memo| memo::render(state, &memo, is_whale(state, transaction, cmc_burns)))
+        .unwrap_or_default();
+    let msg = match operation {
+        Operation::Transfer {
+            from,
+            to,
+            amount,
+            spender: None,
+            ..
+        } => format!(
+            "{} transferred from {} to {}{}",
+            amount_with_usd(state, *amount),
+            account(state, from),
+            account(state, to),
+            memo
+        ),
+        Operation::Transfer {
+            from,
+            to,
+            amount,
+            spender: Some(spender),
+            ..
+        } => format!(
+            "{} transferred from {} to {} by spender {}{}",
+            amount_with_usd(state, *amount),
+            account(state, from),
+            account(state, to),
+            account(state, spender),
+            memo
+        ),
+        Operation::Mint { to, amount } => format!(
+            "{} minted to {}",
+            amount_with_usd(state, *amount),
+            account(state, to)
+        ),
+        Operation::Burn { from, amount, .. } => format!(
+            "{} burned from {}{}",
+            amount_with_usd(state, *amount),
+            account(state, from),
+            memo
+        ),
+        Operation::Approve {
+            from,
+            spender,
+            allowance,
+            ..
+        } => format!(
+            "{} approved by {} for spending by {}",
+            amount_with_usd(state, *allowance),
+            account(state, from),
+            account(state, spender)
+        ),
+    };
+    Some(msg)
+}
+
+/// Checks whether the transaction exceeds the threshold of its type.
+fn is_whale(
+    state: &State,
+    transaction: &Transaction,
+    cmc_burns: &HashSet<AccountIdentifier>,
+) -> bool {
+    let (Some(amount), Some(threshold)) = (
+        transaction_amount(transaction),
+        threshold(state, transaction, cmc_burns),
+    ) else {
+        return false;
+    };
+    let tokens = amount.e8s() as f64 / Tokens::SUBDIVIDABLE_BY as f64;
+    exchangerate::exceeds(state, "ICP", tokens, threshold.usd, threshold.icp)
+}
+
+/// Renders an alert line for the operation of the block if it exceeds the threshold of its type.
+fn alert(
+    state: &State,
+    block: &Block,
+    cmc_burns: &HashSet<AccountIdentifier>,
+    alerts: &mut Alerts,
+) {
+    let Some(amount) = amount(block) else {
+        return;
+    };
+    if !is_whale(state, &block.transaction, cmc_burns) {
+        return;
+    }
+    if let (
+        Some(Operation::Transfer {
+            from,
+            to,
+            spender: None,
+            ..
+        }),
+        None,
+    ) = (
+        block.transaction.operation.as_ref(),
+        purpose(state, &block.transaction, cmc_burns),
+    ) {
+        alerts.add_transfer(*from, *to, amount, memo::decode(&block.transaction));
+    } else if let Some(msg) = describe(state, &block.transaction, cmc_burns) {
+        alerts.alerts.push(Alert::Line(format!("- {}.", msg)));
+    }
+}
+
+/// Groups the lines into chunks not exceeding the maximal length, unless a line is longer itself.
+fn chunks(lines: &[String], max_len: usize) -> Vec<String> {
+    let mut chunks: Vec<String> = Vec::new();
+    for line in lines {
+        match chunks.last_mut() {
+            Some(chunk) if chunk.len() + 1 + line.len() <= max_len => {
+                chunk.push('\n');
+                chunk.push_str(line);
+            }
+            _ => chunks.push(line.clone()),
+        }
+    }
+    chunks
+}
+
+/// Posts the lines under the header, continuing lines beyond the size of one post in replies.
+pub fn schedule_thread(state: &mut State, header: &str, lines: &[String], realm: &str) {
+    let header = format!("{}\n\n", header);
+    let mut chunks = chunks(lines, MAX_POST_LEN - header.len()).into_iter();
+    let Some(root) = chunks.next() else {
+        return;
+    };
+    let Some(id) = schedule_message(state, header + &root, Some(realm.into())) else {
+        return;
+    };
+    for chunk in chunks {
+        schedule_reply(state, id, chunk, Some(realm.into()));
+    }
+}
+
+/// Renders an alert line for every watched account involved in the operation of the block, with
+/// the realm of the watched account.
+fn watch(
+    state: &State,
+    block: &Block,
+    cmc_burns: &HashSet<AccountIdentifier>,
+) -> Vec<(String, String)> {
+    let (Some(operation), Some(msg)) = (
+        block.transaction.operation.as_ref(),
+        describe(state, &block.transaction, cmc_burns),
+    ) else {
+        return Default::default();
+    };
+    // Keyed by account so that a transfer between two watched accounts is reported for both,
+    // while a self-transfer is reported once.
+    accounts(operation)
+        .into_iter()
+        .filter_map(|account| {
+            let account = account.to_string();
+            state.watchlist.get(&account).map(|entry| (account, entry))
+        })
+        .collect::<BTreeMap<_, _>>()
+        .into_values()
+        .map(|entry| {
+            (
+                entry
+                    .realm
+                    .clone()
+                    .unwrap_or_else(|| WATCHLIST_REALM.into()),
+                format!("- {} ({}).", msg, entry.note),
+            )
+        })
+        .collect()
 }
 
 fn icp(tokens: Tokens) -> String {
     (tokens.e8s() / Tokens::SUBDIVIDABLE_BY).to_formatted_string(&Locale::de_CH)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::chunks;
+
+    #[test]
+    fn test_chunks() {
+        let lines = ["aaaa", "bbb", "cc", "dddddddddd", "e"]
+            .iter()
+            .map(|line| line.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            chunks(&lines, 8),
+            vec!["aaaa\nbbb", "cc", "dddddddddd", "e"]
+        );
+        assert_eq!(chunks(&lines, 100), vec![lines.join("\n")]);
+        assert!(chunks(&[], 8).is_empty());
+    }
+}
@@ -0,0 +1,112 @@
+use crate::{controller_only, mutate, read};
+
+use super::schedule_message;
+use candid::CandidType;
+use ic_ledger_types::MAINNET_CYCLES_MINTING_CANISTER_ID;
+use serde::Deserialize;
+
+const REALM: &str = "ICP";
+// Samples kept in the state.
+const MAX_SAMPLES: usize = 1000;
+// Default change since the last announcement that triggers a post, in percent.
+const DEFAULT_THRESHOLD_PERCENT: f64 = 5.0;
+// 1 XDR buys 1T cycles.
+const PERMYRIAD: f64 = 10_000.0;
+
+#[derive(CandidType, Deserialize)]
+struct IcpXdrConversionRate {
+    xdr_permyriad_per_icp: u64,
+    timestamp_seconds: u64,
+}
+
+// The certificate and hash tree are omitted.
+#[derive(CandidType, Deserialize)]
+struct IcpXdrConversionRateResponse {
+    data: IcpXdrConversionRate,
+}
+
+/// Renders the rate with its change since the previously announced rate.
+fn message(rate: u64, previous: u64) -> String {
+    format!(
+        "💱 The #ICP/XDR conversion rate is now `{:.4}` XDR per ICP ({:+.1}% since `{:.4}`): 1T cycles cost `{:.4}` ICP",
+        rate as f64 / PERMYRIAD,
+        (rate as f64 - previous as f64) * 100.0 / previous as f64,
+        previous as f64 / PERMYRIAD,
+        PERMYRIAD / rate as f64
+    )
+}
+
+/// Checks whether the rate moved by at least the threshold since the previous rate.
+fn moved(rate: u64, previous: u64, threshold_percent: f64) -> bool {
+    previous > 0
+        && (rate as f64 - previous as f64).abs() * 100.0 / previous as f64 >= threshold_percent
+}
+
+/// Records the current rate and announces it if it moved beyond the threshold since the last
+/// announcement.
+pub async fn go() -> Result<(), String> {
+    let (response,): (IcpXdrConversionRateResponse,) = ic_cdk::call(
+        MAINNET_CYCLES_MINTING_CANISTER_ID,
+        "get_icp_xdr_conversion_rate",
+        (),
+    )
+    .await
+    .map_err(|err| format!("couldn't get the ICP/XDR rate: {:?}", err))?;
+    let IcpXdrConversionRate {
+        xdr_permyriad_per_icp: rate,
+        timestamp_seconds: timestamp,
+    } = response.data;
+    mutate(|s| {
+        if s.xdr_rates.back().map(|(last, _)| *last) != Some(timestamp) {
+            s.xdr_rates.push_back((timestamp, rate));
+            while s.xdr_rates.len() > MAX_SAMPLES {
+                s.xdr_rates.pop_front();
+            }
+        }
+        let threshold = s.xdr_rate_threshold.unwrap_or(DEFAULT_THRESHOLD_PERCENT);
+        // The first rate is only remembered.
+        if s.last_announced_xdr_rate == 0 {
+            s.last_announced_xdr_rate = rate;
+        } else if moved(rate, s.last_announced_xdr_rate, threshold) {
+            let msg = message(rate, s.last_announced_xdr_rate);
+            schedule_message(s, msg, Some(REALM.into()));
+            s.last_announced_xdr_rate = rate;
+        }
+    });
+    Ok(())
+}
+
+/// Sets the change of the rate in percent since the last announcement that triggers a post.
+#[ic_cdk_macros::update]
+fn set_xdr_rate_threshold(percent: f64) -> Result<(), String> {
+    controller_only()?;
+    if !percent.is_finite() || percent <= 0.0 {
+        return Err("the threshold must be positive".into());
+    }
+    mutate(|s| s.xdr_rate_threshold = Some(percent));
+    Ok(())
+}
+
+/// Returns the recorded rates as (timestamp in seconds, XDR permyriad per ICP).
+#[ic_cdk_macros::query]
+fn xdr_rate_history() -> Vec<(u64, u64)> {
+    read(|s| s.xdr_rates.iter().copied().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{message, moved};
+
+    #[test]
+    fn test_rate() {
+        assert!(!moved(40_000, 0, 5.0));
+        assert!(!moved(41_000, 40_000, 5.0));
+        assert!(moved(42_000, 40_000, 5.0));
+        assert!(moved(38_000, 40_000, 5.0));
+        assert_eq!(
+            message(50_000, 40_000),
+            "💱 The #ICP/XDR conversion rate is now `5.0000` XDR per ICP (+25.0% since `4.0000`): \
+             1T cycles cost `0.2000` ICP"
+        );
+    }
+}